@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// On-disk cache of GET responses keyed by url and token, used to send
+/// `If-None-Match` and reuse the body on `304 Not Modified`. Failures to read
+/// or write the cache are ignored, it only ever makes requests faster.
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub etag: String,
+    pub body: String,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cache is opt-in until it has proven itself, enable it by setting
+    /// MOLNETT_HTTP_CACHE=1.
+    pub fn enabled() -> bool {
+        std::env::var("MOLNETT_HTTP_CACHE").is_ok_and(|v| v == "1")
+    }
+
+    pub fn get(&self, url: &str, token: &str) -> Option<CachedResponse> {
+        let content = fs::read_to_string(self.entry_path(url, token)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn put(&self, url: &str, token: &str, etag: &str, body: &str) {
+        let entry = CachedResponse {
+            etag: etag.to_string(),
+            body: body.to_string(),
+        };
+        if let Ok(content) = serde_json::to_string(&entry) {
+            let _ = self.write_entry(&self.entry_path(url, token), &content);
+        }
+    }
+
+    /// Bodies can hold anything the token may read, so entries live in a
+    /// directory only the user can open, also when an older molnctl created
+    /// it, and are written to a private temp file that is renamed into place.
+    /// A concurrent molnctl never reads a half-written entry.
+    fn write_entry(&self, path: &Path, content: &str) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o700))?;
+        }
+
+        let mut tmp = tempfile::Builder::new()
+            .prefix(".")
+            .suffix(".tmp")
+            .tempfile_in(&self.dir)?;
+        tmp.write_all(content.as_bytes())?;
+        tmp.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    /// Drops every entry. Called on any mutating request since a single
+    /// write can change the result of several list calls.
+    pub fn clear(&self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+
+    /// Named after a SHA-256 of the url and token, so the token never ends up
+    /// in a file name and names stay the same across Rust versions.
+    fn entry_path(&self, url: &str, token: &str) -> PathBuf {
+        let hash = Sha256::new()
+            .chain_update(url)
+            .chain_update([0])
+            .chain_update(token)
+            .finalize();
+        self.dir.join(format!("{:x}.json", hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_keyed_by_url_and_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path().join("cache"));
+        cache.put("https://api/envs", "t1", "\"1\"", "[\"prod\"]");
+
+        let entry = cache.get("https://api/envs", "t1").unwrap();
+        assert_eq!((entry.etag.as_str(), entry.body.as_str()), ("\"1\"", "[\"prod\"]"));
+        assert!(cache.get("https://api/envs", "t2").is_none());
+        assert!(cache.get("https://api/svcs", "t1").is_none());
+
+        cache.clear();
+        assert!(cache.get("https://api/envs", "t1").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path().join("cache"));
+        cache.put("https://api/envs", "t", "\"1\"", "[]");
+
+        let mode = |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&cache.dir), 0o700);
+        let entries = fs::read_dir(&cache.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec![cache.entry_path("https://api/envs", "t")]);
+        assert_eq!(mode(&entries[0]), 0o600);
+    }
+}
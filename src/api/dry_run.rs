@@ -0,0 +1,163 @@
+use super::types::*;
+use super::MolnettApi;
+use std::time::Duration;
+
+/// Wraps another client and turns every mutating call into a no-op that only
+/// prints what would have been done. Read-only calls are passed through.
+pub struct DryRunClient {
+    inner: Box<dyn MolnettApi>,
+}
+
+impl DryRunClient {
+    pub fn new(inner: Box<dyn MolnettApi>) -> Self {
+        Self { inner }
+    }
+}
+
+impl MolnettApi for DryRunClient {
+    fn get_org(&self, token: &str, org_name: &str) -> anyhow::Result<Organization> {
+        self.inner.get_org(token, org_name)
+    }
+
+    fn get_organizations(&self, token: &str) -> anyhow::Result<ListOrganizationResponse> {
+        self.inner.get_organizations(token)
+    }
+
+    fn get_service(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+        name: &str,
+    ) -> anyhow::Result<Option<Service>> {
+        self.inner.get_service(token, org_name, env_name, name)
+    }
+
+    fn get_services(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+    ) -> anyhow::Result<ListServicesResponse> {
+        self.inner.get_services(token, org_name, env_name)
+    }
+
+    fn create_organization(
+        &self,
+        _token: &str,
+        name: &str,
+        billing_email: &str,
+    ) -> anyhow::Result<Organization> {
+        println!(
+            "[dry-run] would create organization {} with billing email {}",
+            name, billing_email
+        );
+        Ok(Organization {
+            id: "".to_string(),
+            name: name.to_string(),
+            billing_email: billing_email.to_string(),
+        })
+    }
+
+    fn get_environments(&self, token: &str, org_name: &str) -> anyhow::Result<Vec<String>> {
+        self.inner.get_environments(token, org_name)
+    }
+
+    fn create_environment(
+        &self,
+        _token: &str,
+        name: &str,
+        org_name: &str,
+        copy_from: Option<&str>,
+    ) -> anyhow::Result<CreateEnvironmentResponse> {
+        match copy_from {
+            Some(copy_from) => println!(
+                "[dry-run] would create environment {} in org {} as a copy of {}",
+                name, org_name, copy_from
+            ),
+            None => println!(
+                "[dry-run] would create environment {} in org {}",
+                name, org_name
+            ),
+        }
+        Ok(CreateEnvironmentResponse {
+            name: name.to_string(),
+            copy_from: DisplayOption(copy_from.map(|c| c.to_string())),
+        })
+    }
+
+    fn delete_environment(&self, _token: &str, org_name: &str, name: &str) -> anyhow::Result<()> {
+        println!(
+            "[dry-run] would delete environment {} in org {}",
+            name, org_name
+        );
+        Ok(())
+    }
+
+    fn deploy_service(
+        &self,
+        _token: &str,
+        org_name: &str,
+        env_name: &str,
+        service: Service,
+        _timeout: Option<Duration>,
+    ) -> anyhow::Result<Service> {
+        println!(
+            "[dry-run] would deploy service {} ({}) to environment {} in org {}",
+            service.name, service.image, env_name, org_name
+        );
+        Ok(service)
+    }
+
+    fn delete_service(
+        &self,
+        _token: &str,
+        org_name: &str,
+        env_name: &str,
+        svc_name: &str,
+    ) -> anyhow::Result<()> {
+        println!(
+            "[dry-run] would delete service {} from environment {} in org {}",
+            svc_name, env_name, org_name
+        );
+        Ok(())
+    }
+
+    fn get_secrets(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+    ) -> anyhow::Result<ListSecretsResponse> {
+        self.inner.get_secrets(token, org_name, env_name)
+    }
+
+    fn create_secret(
+        &self,
+        _token: &str,
+        org_name: &str,
+        env_name: &str,
+        name: &str,
+        _value: &str,
+    ) -> anyhow::Result<()> {
+        println!(
+            "[dry-run] would set secret {} in environment {} in org {}",
+            name, env_name, org_name
+        );
+        Ok(())
+    }
+
+    fn delete_secret(
+        &self,
+        _token: &str,
+        org_name: &str,
+        env_name: &str,
+        secret_name: &str,
+    ) -> anyhow::Result<()> {
+        println!(
+            "[dry-run] would delete secret {} from environment {} in org {}",
+            secret_name, env_name, org_name
+        );
+        Ok(())
+    }
+}
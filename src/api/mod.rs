@@ -1,27 +1,388 @@
 use anyhow::{anyhow, Context};
+use once_cell::sync::OnceCell;
 use reqwest::{blocking::Response, StatusCode};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
+use std::io::Read;
+use std::time::Duration;
+use thiserror::Error;
 
+use self::cache::ResponseCache;
+use self::proxy::ProxyOptions;
+use self::tls::TlsOptions;
 use self::types::*;
+use crate::util::Masked;
 
+pub mod cache;
+pub mod dry_run;
+pub mod proxy;
+pub mod tls;
+pub mod websocket;
 pub mod types;
 
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("Unauthorized, please login first")]
+    Unauthorized,
+    #[error("{0}")]
+    NotFound(String),
+    #[error(
+        "Rate limited by the API{}",
+        .0.map(|d| format!(", retry after {}s", d.as_secs())).unwrap_or_default()
+    )]
+    TooManyRequests(Option<Duration>),
+}
+
+/// The Molnett API as used by the commands. Implemented by `APIClient`;
+/// commands only depend on this trait so the transport can be swapped out.
+pub trait MolnettApi: Send + Sync {
+    fn get_org(
+        &self,
+        token: &str,
+        org_name: &str,
+    ) -> anyhow::Result<Organization>;
+
+    fn get_organizations(
+        &self,
+        token: &str,
+    ) -> anyhow::Result<ListOrganizationResponse>;
+
+    /// `None` when the environment exists but has no service called `name`.
+    fn get_service(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+        name: &str,
+    ) -> anyhow::Result<Option<Service>>;
+
+    fn get_services(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+    ) -> anyhow::Result<ListServicesResponse>;
+
+    fn create_organization(
+        &self,
+        token: &str,
+        name: &str,
+        billing_email: &str,
+    ) -> anyhow::Result<Organization>;
+
+    fn get_environments(
+        &self,
+        token: &str,
+        org_name: &str,
+    ) -> anyhow::Result<Vec<String>>;
+
+    fn create_environment(
+        &self,
+        token: &str,
+        name: &str,
+        org_name: &str,
+        copy_from: Option<&str>,
+    ) -> anyhow::Result<CreateEnvironmentResponse>;
+
+    fn delete_environment(
+        &self,
+        token: &str,
+        org_name: &str,
+        name: &str,
+    ) -> anyhow::Result<()>;
+
+    fn deploy_service(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+        service: Service,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Service>;
+
+    fn delete_service(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+        svc_name: &str,
+    ) -> anyhow::Result<()>;
+
+    fn get_secrets(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+    ) -> anyhow::Result<ListSecretsResponse>;
+
+    fn create_secret(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+        name: &str,
+        value: &str,
+    ) -> anyhow::Result<()>;
+
+    fn delete_secret(
+        &self,
+        token: &str,
+        org_name: &str,
+        env_name: &str,
+        secret_name: &str,
+    ) -> anyhow::Result<()>;
+}
+
 pub struct APIClient {
     client: reqwest::blocking::Client,
     base_url: String,
     user_agent: String,
+    cache: Option<ResponseCache>,
+}
+
+/// The process wide HTTP client. reqwest clients hold a connection pool, so
+/// sharing one lets a command that makes several calls, even through several
+/// `APIClient`s, send them over one kept-alive connection instead of opening
+/// a new one per client.
+fn http_client() -> reqwest::blocking::Client {
+    static CLIENT: OnceCell<reqwest::blocking::Client> = OnceCell::new();
+    CLIENT.get_or_init(reqwest::blocking::Client::new).clone()
 }
 
 impl APIClient {
     pub fn new(base_url: impl AsRef<str>) -> Self {
         Self {
-            client: reqwest::blocking::Client::new(),
+            client: http_client(),
             base_url: base_url.as_ref().to_string(),
             user_agent: format!("molnctl/{}", env!("CARGO_PKG_VERSION")),
+            cache: None,
+        }
+    }
+
+    /// Talks to the API with custom TLS or proxy settings instead of the
+    /// shared client.
+    pub fn with_connection(mut self, tls: &TlsOptions, proxy: &ProxyOptions) -> anyhow::Result<Self> {
+        if !tls.is_default() || !proxy.is_default() {
+            let builder = tls.configure(reqwest::blocking::Client::builder())?;
+            self.client = proxy.configure(builder)?.build()?;
         }
+        Ok(self)
+    }
+
+    /// Caches list responses in `cache` and revalidates them with ETags.
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
     }
 
-    pub fn get_org(
+    /// GETs `url` and returns the status and body, answering from the cache
+    /// when the API reports the cached body as still current.
+    fn get_cached(&self, url: &str, token: &str) -> anyhow::Result<(StatusCode, String)> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => {
+                let response = self.get(url, token)?;
+                return Ok((response.status(), read_body(response)?));
+            }
+        };
+
+        let cached = cache.get(url, token);
+        let mut request = self
+            .client
+            .get(url)
+            .header("User-Agent", self.user_agent.as_str())
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json");
+        if let Some(cached) = &cached {
+            request = request.header("If-None-Match", cached.etag.as_str());
+        }
+        let response = check_rate_limit(request.send()?)?;
+
+        match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => Ok((StatusCode::OK, cached.body)),
+            (status, _) => {
+                let etag = response
+                    .headers()
+                    .get("ETag")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string);
+                let body = read_body(response)?;
+                if let (StatusCode::OK, Some(etag)) = (status, etag) {
+                    cache.put(url, token, &etag, &body);
+                }
+                Ok((status, body))
+            }
+        }
+    }
+
+    fn invalidate_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    fn get(&self, url: &str, token: &str) -> anyhow::Result<Response> {
+        let response = self
+            .client
+            .get(url)
+            .header("User-Agent", self.user_agent.as_str())
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .send()?;
+        check_rate_limit(response)
+    }
+
+    fn put(
+        &self,
+        url: &str,
+        token: &str,
+        body: &HashMap<&str, &str>,
+    ) -> anyhow::Result<Response> {
+        self.invalidate_cache();
+        let response = self
+            .client
+            .put(url)
+            .header("User-Agent", self.user_agent.as_str())
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .json(&body)
+            .send()?;
+        check_rate_limit(response)
+    }
+
+    fn post(
+        &self,
+        url: &str,
+        token: &str,
+        body: &HashMap<&str, &str>,
+    ) -> anyhow::Result<Response> {
+        self.invalidate_cache();
+        let response = self
+            .client
+            .post(url)
+            .header("User-Agent", self.user_agent.as_str())
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .json(&body)
+            .send()?;
+        check_rate_limit(response)
+    }
+
+    fn post_str(
+        &self,
+        url: &str,
+        token: &str,
+        body: String,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Response> {
+        self.invalidate_cache();
+        let mut request = self
+            .client
+            .post(url)
+            .header("User-Agent", self.user_agent.as_str())
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .body(body);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        check_rate_limit(request.send()?)
+    }
+
+    fn delete(&self, url: &str, token: &str) -> anyhow::Result<Response> {
+        self.invalidate_cache();
+        let response = self
+            .client
+            .delete(url)
+            .header("User-Agent", self.user_agent.as_str())
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .send()?;
+        check_rate_limit(response)
+    }
+}
+
+/// Upper bound on the size of a response body we are willing to read, so a
+/// runaway endpoint produces an error instead of exhausting memory.
+/// Can be overridden with MOLNETT_MAX_RESPONSE_SIZE (in bytes).
+const DEFAULT_MAX_RESPONSE_SIZE: u64 = 32 * 1024 * 1024;
+
+fn max_response_size() -> u64 {
+    std::env::var("MOLNETT_MAX_RESPONSE_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RESPONSE_SIZE)
+}
+
+fn read_body(response: Response) -> anyhow::Result<String> {
+    let limit = max_response_size();
+    let url = response.url().clone();
+    let too_large = || {
+        anyhow!(
+            "Response from {} exceeds the maximum size of {} bytes, set MOLNETT_MAX_RESPONSE_SIZE to raise the limit",
+            url,
+            limit
+        )
+    };
+    if response.content_length().is_some_and(|len| len > limit) {
+        return Err(too_large());
+    }
+
+    let mut body = String::new();
+    response.take(limit + 1).read_to_string(&mut body)?;
+    if body.len() as u64 > limit {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+/// How much of a response body to include in errors, so a misbehaving
+/// endpoint doesn't dump megabytes into the terminal.
+const MAX_ERROR_BODY_LEN: usize = 512;
+
+fn deserialize<T: DeserializeOwned>(body: &str, what: &str) -> anyhow::Result<T> {
+    serde_json::from_str(body).with_context(|| {
+        format!(
+            "Failed to deserialize {}. API returned: {}",
+            what,
+            truncate_body(body)
+        )
+    })
+}
+
+fn truncate_body(body: &str) -> String {
+    if body.len() <= MAX_ERROR_BODY_LEN {
+        return body.to_string();
+    }
+    let mut end = MAX_ERROR_BODY_LEN;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes total)", &body[..end], body.len())
+}
+
+/// Turns a 429 into `ApiError::TooManyRequests`, so every call reports rate
+/// limiting the same way and `run_bulk` can retry it.
+fn check_rate_limit(response: Response) -> anyhow::Result<Response> {
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        return Err(ApiError::TooManyRequests(retry_after(&response)).into());
+    }
+    Ok(response)
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+impl MolnettApi for APIClient {
+    fn get_org(
         &self,
         token: &str,
         org_name: &str,
@@ -29,28 +390,35 @@ impl APIClient {
         let url = format!("{}/orgs/{}", self.base_url, org_name);
         let response = self.get(&url, token)?;
         match response.status() {
-            StatusCode::OK => Ok(serde_json::from_str(&response.text()?)
-                .with_context(|| "Failed to deserialize org")?),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
-            StatusCode::NOT_FOUND => Err(anyhow!("Org not found")),
+            StatusCode::OK => deserialize(&read_body(response)?, "org"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Org not found".to_string()).into()),
             _ => Err(anyhow!(
                 "Failed to get org. API returned {} {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn get_organizations(
+    fn get_organizations(
         &self,
         token: &str,
-    ) -> Result<ListOrganizationResponse, reqwest::Error> {
+    ) -> anyhow::Result<ListOrganizationResponse> {
         let url = format!("{}/orgs", self.base_url);
-        let response = self.get(&url, token)?.error_for_status()?;
-        response.json()
+        let (status, body) = self.get_cached(&url, token)?;
+        match status {
+            StatusCode::OK => deserialize(&body, "organizations"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            _ => Err(anyhow!(
+                "Failed to list organizations. API returned {} {}",
+                status,
+                truncate_body(&body)
+            )),
+        }
     }
 
-    pub fn get_service(
+    fn get_service(
         &self,
         token: &str,
         org_name: &str,
@@ -63,30 +431,46 @@ impl APIClient {
         );
         let response = self.get(&url, token)?;
         match response.status() {
-            StatusCode::OK => Ok(serde_json::from_str(&response.text()?)
-                .with_context(|| "Failed to deserialize service")?),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
-            StatusCode::NOT_FOUND => Ok(None),
+            StatusCode::OK => deserialize(&read_body(response)?, "service"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            // A 404 is only "no such service yet" if the environment exists,
+            // otherwise a deploy would go ahead as a create.
+            StatusCode::NOT_FOUND => {
+                if self.get_environments(token, org_name)?.iter().any(|env| env == env_name) {
+                    Ok(None)
+                } else {
+                    Err(ApiError::NotFound(format!("Environment {} does not exist", env_name)).into())
+                }
+            }
             _ => Err(anyhow!(
                 "Failed to get service. API returned {} {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn get_services(
+    fn get_services(
         &self,
         token: &str,
         org_name: &str,
         env_name: &str,
     ) -> anyhow::Result<ListServicesResponse> {
         let url = format!("{}/orgs/{}/envs/{}/svcs", self.base_url, org_name, env_name);
-        let response: String = self.get(&url, token)?.error_for_status()?.text()?;
-        serde_json::from_str(response.as_str()).with_context(|| "Failed to deserialize response")
+        let (status, body) = self.get_cached(&url, token)?;
+        match status {
+            StatusCode::OK => deserialize(&body, "services list"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Environment does not exist".to_string()).into()),
+            _ => Err(anyhow!(
+                "Failed to list services. API returned {} {}",
+                status,
+                truncate_body(&body)
+            )),
+        }
     }
 
-    pub fn create_organization(
+    fn create_organization(
         &self,
         token: &str,
         name: &str,
@@ -98,41 +482,39 @@ impl APIClient {
         body.insert("billing_email", billing_email);
         let response = self.post(&url, token, &body)?;
         match response.status() {
-            StatusCode::CREATED => Ok(serde_json::from_str(&response.text()?)
-                .with_context(|| "Failed to deserialize org")?),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
+            StatusCode::CREATED => deserialize(&read_body(response)?, "org"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
             StatusCode::CONFLICT => Err(anyhow!("Organization already exists")),
-            StatusCode::NOT_FOUND => Err(anyhow!("Org not found")),
-            StatusCode::BAD_REQUEST => Err(anyhow!("Bad request: {}", response.text()?)),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Org not found".to_string()).into()),
+            StatusCode::BAD_REQUEST => Err(anyhow!("Bad request: {}", truncate_body(&read_body(response)?))),
             _ => Err(anyhow!(
                 "Failed to deploy service. API returned {} - {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn get_environments(
+    fn get_environments(
         &self,
         token: &str,
         org_name: &str,
     ) -> anyhow::Result<Vec<String>> {
         let url = format!("{}/orgs/{}/envs", self.base_url, org_name);
-        let response = self.get(&url, token)?;
-        match response.status() {
-            StatusCode::OK => Ok(serde_json::from_str(&response.text()?)
-                .with_context(|| "Failed to deserialize environments")?),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
-            StatusCode::NOT_FOUND => Err(anyhow!("Organization does not exist")),
+        let (status, body) = self.get_cached(&url, token)?;
+        match status {
+            StatusCode::OK => deserialize(&body, "environments"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Organization does not exist".to_string()).into()),
             _ => Err(anyhow!(
                 "Failed to get environments. API returned {} {}",
-                response.status(),
-                response.text()?
+                status,
+                truncate_body(&body)
             )),
         }
     }
 
-    pub fn create_environment(
+    fn create_environment(
         &self,
         token: &str,
         name: &str,
@@ -147,21 +529,20 @@ impl APIClient {
         }
         let response = self.post(&url, token, &body)?;
         match response.status() {
-            StatusCode::CREATED => Ok(serde_json::from_str(&response.text()?)
-                .with_context(|| "Failed to deserialize env")?),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
+            StatusCode::CREATED => deserialize(&read_body(response)?, "env"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
             StatusCode::CONFLICT => Err(anyhow!("Environment already exists")),
-            StatusCode::NOT_FOUND => Err(anyhow!("Org not found")),
-            StatusCode::BAD_REQUEST => Err(anyhow!("Bad request: {}", response.text()?)),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Org not found".to_string()).into()),
+            StatusCode::BAD_REQUEST => Err(anyhow!("Bad request: {}", truncate_body(&read_body(response)?))),
             _ => Err(anyhow!(
                 "Failed to create environment. API returned {} - {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn delete_environment(
+    fn delete_environment(
         &self,
         token: &str,
         org_name: &str,
@@ -171,40 +552,40 @@ impl APIClient {
         let response = self.delete(&url, token)?;
         match response.status() {
             StatusCode::NO_CONTENT => Ok(()),
-            StatusCode::NOT_FOUND => Err(anyhow!("Environment does not exist")),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Environment does not exist".to_string()).into()),
             _ => Err(anyhow!(
                 "Failed to delete environment. API returned {} - {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn deploy_service(
+    fn deploy_service(
         &self,
         token: &str,
         org_name: &str,
         env_name: &str,
         service: Service,
+        timeout: Option<Duration>,
     ) -> anyhow::Result<Service> {
         let url = format!("{}/orgs/{}/envs/{}/svcs", self.base_url, org_name, env_name);
         let body = serde_json::to_string(&service)?;
-        let response = self.post_str(&url, token, body)?;
+        let response = self.post_str(&url, token, body, timeout)?;
         match response.status() {
-            StatusCode::CREATED => Ok(serde_json::from_str(&response.text()?)
-                .with_context(|| "Failed to deserialize service")?),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
-            StatusCode::NOT_FOUND => Err(anyhow!("Org or environment not found")),
-            StatusCode::BAD_REQUEST => Err(anyhow!("Bad request: {}", response.text()?)),
+            StatusCode::CREATED => deserialize(&read_body(response)?, "service"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Org or environment not found".to_string()).into()),
+            StatusCode::BAD_REQUEST => Err(anyhow!("Bad request: {}", truncate_body(&read_body(response)?))),
             _ => Err(anyhow!(
                 "Failed to deploy service. API returned {} - {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn delete_service(
+    fn delete_service(
         &self,
         token: &str,
         org_name: &str,
@@ -218,16 +599,16 @@ impl APIClient {
         let response = self.delete(&url, token)?;
         match response.status() {
             StatusCode::NO_CONTENT => Ok(()),
-            StatusCode::NOT_FOUND => Err(anyhow!("Service does not exist")),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Service does not exist".to_string()).into()),
             _ => Err(anyhow!(
                 "Failed to delete service. API returned {} - {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn get_secrets(
+    fn get_secrets(
         &self,
         token: &str,
         org_name: &str,
@@ -239,19 +620,18 @@ impl APIClient {
         );
         let response = self.get(&url, token)?;
         match response.status() {
-            StatusCode::OK => Ok(serde_json::from_str(&response.text()?)
-                .with_context(|| "Failed to deserialize secrets list")?),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
-            StatusCode::NOT_FOUND => Err(anyhow!("Org or environment not found")),
+            StatusCode::OK => deserialize(&read_body(response)?, "secrets list"),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Org or environment not found".to_string()).into()),
             _ => Err(anyhow!(
                 "Failed to get secrets. API returned {} - {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
 
-    pub fn create_secret(
+    fn create_secret(
         &self,
         token: &str,
         org_name: &str,
@@ -268,17 +648,23 @@ impl APIClient {
         let response = self.put(&url, token, &body)?;
         match response.status() {
             StatusCode::NO_CONTENT => Ok(()),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized, please login first")),
-            StatusCode::NOT_FOUND => Err(anyhow!("Org or environment not found")),
-            _ => Err(anyhow!(
-                "Failed to create secret. API returned {} - {}",
-                response.status(),
-                response.text()?
-            )),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized.into()),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Org or environment not found".to_string()).into()),
+            status => {
+                // Validation errors may quote the rejected value back.
+                match Masked(value).scrub(&read_body(response)?) {
+                    Some(body) => Err(anyhow!(
+                        "Failed to create secret. API returned {} - {}",
+                        status,
+                        truncate_body(&body)
+                    )),
+                    None => Err(anyhow!("Failed to create secret. API returned {}", status)),
+                }
+            }
         }
     }
 
-    pub fn delete_secret(
+    fn delete_secret(
         &self,
         token: &str,
         org_name: &str,
@@ -292,75 +678,205 @@ impl APIClient {
         let response = self.delete(&url, token)?;
         match response.status() {
             StatusCode::NO_CONTENT => Ok(()),
-            StatusCode::NOT_FOUND => Err(anyhow!("Secret does not exist")),
+            StatusCode::NOT_FOUND => Err(ApiError::NotFound("Secret does not exist".to_string()).into()),
             _ => Err(anyhow!(
                 "Failed to delete secret. API returned {} - {}",
                 response.status(),
-                response.text()?
+                truncate_body(&read_body(response)?)
             )),
         }
     }
+}
 
-    fn get(&self, url: &str, token: &str) -> Result<Response, reqwest::Error> {
-        return self
-            .client
-            .get(url)
-            .header("User-Agent", self.user_agent.as_str())
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", "application/json")
-            .send();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::JoinHandle;
+
+    /// A request received by `serve`.
+    struct Recorded {
+        method: String,
+        url: String,
+        headers: HashMap<String, String>,
+        body: String,
     }
 
-    fn put(
-        &self,
-        url: &str,
-        token: &str,
-        body: &HashMap<&str, &str>,
-    ) -> Result<Response, reqwest::Error> {
-        return self
-            .client
-            .put(url)
-            .header("User-Agent", self.user_agent.as_str())
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send();
+    impl Recorded {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+        }
     }
 
-    fn post(
-        &self,
-        url: &str,
-        token: &str,
-        body: &HashMap<&str, &str>,
-    ) -> Result<Response, reqwest::Error> {
-        return self
-            .client
-            .post(url)
-            .header("User-Agent", self.user_agent.as_str())
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send();
+    /// Answers one request per entry of `responses` on a local port, in
+    /// order, and returns the base url and the requests it received.
+    fn serve(responses: Vec<(u16, &'static str)>) -> (String, JoinHandle<Vec<Recorded>>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let handle = std::thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|(status, body)| {
+                    let mut request = server.recv().unwrap();
+                    let mut content = String::new();
+                    request.as_reader().read_to_string(&mut content).unwrap();
+                    let recorded = Recorded {
+                        method: request.method().to_string(),
+                        url: request.url().to_string(),
+                        headers: request
+                            .headers()
+                            .iter()
+                            .map(|h| (h.field.as_str().as_str().to_ascii_lowercase(), h.value.to_string()))
+                            .collect(),
+                        body: content,
+                    };
+                    request
+                        .respond(tiny_http::Response::from_string(body).with_status_code(status))
+                        .unwrap();
+                    recorded
+                })
+                .collect()
+        });
+        (url, handle)
     }
 
-    fn post_str(&self, url: &str, token: &str, body: String) -> Result<Response, reqwest::Error> {
-        return self
-            .client
-            .post(url)
-            .header("User-Agent", self.user_agent.as_str())
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", "application/json")
-            .body(body)
-            .send();
+    #[test]
+    fn requests_carry_auth_and_client_headers() {
+        let (url, server) = serve(vec![(200, r#"["prod", "staging"]"#)]);
+        let envs = APIClient::new(&url).get_environments("t", "o").unwrap();
+        assert_eq!(envs, vec!["prod", "staging"]);
+
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.url, "/orgs/o/envs");
+        assert_eq!(request.header("Authorization"), Some("Bearer t"));
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert_eq!(
+            request.header("User-Agent"),
+            Some(concat!("molnctl/", env!("CARGO_PKG_VERSION")))
+        );
     }
 
-    fn delete(&self, url: &str, token: &str) -> Result<Response, reqwest::Error> {
-        return self
-            .client
-            .delete(url)
-            .header("User-Agent", self.user_agent.as_str())
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", "application/json")
-            .send();
+    #[test]
+    fn create_environment_posts_name_and_source() {
+        let (url, server) = serve(vec![(201, r#"{"name": "staging", "copy_from": "prod"}"#)]);
+        let env = APIClient::new(&url)
+            .create_environment("t", "staging", "o", Some("prod"))
+            .unwrap();
+        assert_eq!(env.name, "staging");
+
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.url, "/orgs/o/envs");
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body, serde_json::json!({ "name": "staging", "copy_from": "prod" }));
+    }
+
+    #[test]
+    fn create_secret_puts_the_value() {
+        let (url, server) = serve(vec![(204, "")]);
+        APIClient::new(&url).create_secret("t", "o", "prod", "S1", "hunter2").unwrap();
+
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.url, "/orgs/o/envs/prod/secrets/S1");
+        assert_eq!(request.body, r#"{"value":"hunter2"}"#);
+    }
+
+    #[test]
+    fn status_codes_map_to_errors() {
+        let (url, server) = serve(vec![(401, ""), (404, ""), (429, ""), (204, ""), (500, "boom")]);
+        let client = APIClient::new(&url);
+
+        let err = client.get_environments("t", "o").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ApiError::Unauthorized)));
+        let err = client.delete_service("t", "o", "prod", "web").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ApiError::NotFound(msg)) if msg == "Service does not exist"));
+        let err = client.get_secrets("t", "o", "prod").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ApiError::TooManyRequests(None))));
+        client.delete_secret("t", "o", "prod", "S1").unwrap();
+        let err = client.delete_environment("t", "o", "prod").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to delete environment. API returned 500 Internal Server Error - boom"
+        );
+
+        let urls = server.join().unwrap().into_iter().map(|r| (r.method, r.url)).collect::<Vec<_>>();
+        assert_eq!(urls[3], ("DELETE".to_string(), "/orgs/o/envs/prod/secrets/S1".to_string()));
+    }
+
+    #[test]
+    fn create_secret_masks_the_value_in_errors() {
+        let (url, _server) = serve(vec![
+            (400, r#"{"error": "invalid value", "value": "hunter2\"quoted"}"#),
+            (400, "invalid value hunter2"),
+        ]);
+        let client = APIClient::new(&url);
+        let err = client.create_secret("t", "o", "prod", "S1", "hunter2\"quoted").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Failed to create secret. API returned 400 Bad Request - {"error": "invalid value", "value": "****"}"#
+        );
+        // Too short to mask reliably, the body is left out.
+        let err = client.create_secret("t", "o", "prod", "S1", "hunter2").unwrap_err();
+        assert_eq!(err.to_string(), "Failed to create secret. API returned 400 Bad Request");
+    }
+
+    #[test]
+    fn invalid_bodies_are_reported_with_the_body() {
+        let (url, _server) = serve(vec![(200, "<html>gateway</html>")]);
+        let err = APIClient::new(&url).get_environments("t", "o").unwrap_err();
+        assert!(format!("{:#}", err).contains("<html>gateway</html>"));
+    }
+
+    #[test]
+    fn truncate_body_keeps_short_bodies() {
+        assert_eq!(truncate_body(""), "");
+        let body = "x".repeat(MAX_ERROR_BODY_LEN);
+        assert_eq!(truncate_body(&body), body);
+    }
+
+    #[test]
+    fn truncate_body_cuts_long_bodies_on_a_char_boundary() {
+        let body = "x".repeat(MAX_ERROR_BODY_LEN + 10);
+        assert_eq!(
+            truncate_body(&body),
+            format!("{}... ({} bytes total)", "x".repeat(MAX_ERROR_BODY_LEN), MAX_ERROR_BODY_LEN + 10)
+        );
+
+        // 'ö' is two bytes, so byte MAX_ERROR_BODY_LEN falls inside one.
+        let body = format!("x{}", "ö".repeat(MAX_ERROR_BODY_LEN));
+        let truncated = truncate_body(&body);
+        let kept = truncated.split("...").next().unwrap();
+        assert_eq!(kept.len(), MAX_ERROR_BODY_LEN - 1);
+        assert!(truncated.ends_with(&format!("({} bytes total)", body.len())));
+    }
+
+    const SERVICE: &str = r#"{"name": "web", "image": "a:1", "container_port": 80}"#;
+
+    #[test]
+    fn get_service_404_in_existing_environment_is_none() {
+        let (url, server) = serve(vec![(404, "not found"), (200, r#"["prod"]"#)]);
+        let service = APIClient::new(&url).get_service("t", "o", "prod", "web").unwrap();
+        assert!(service.is_none());
+
+        let urls = server.join().unwrap().into_iter().map(|r| r.url).collect::<Vec<_>>();
+        assert_eq!(urls, vec!["/orgs/o/envs/prod/svcs/web", "/orgs/o/envs"]);
+    }
+
+    #[test]
+    fn get_service_404_in_missing_environment_is_an_error() {
+        let (url, _server) = serve(vec![(404, "not found"), (200, r#"["prod"]"#)]);
+        let err = APIClient::new(&url).get_service("t", "o", "prdo", "web").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ApiError::NotFound(msg)) if msg == "Environment prdo does not exist"
+        ));
+    }
+
+    #[test]
+    fn get_service_returns_the_service() {
+        let (url, _server) = serve(vec![(200, SERVICE)]);
+        let service = APIClient::new(&url).get_service("t", "o", "prod", "web").unwrap();
+        assert_eq!(service.unwrap().image, "a:1");
     }
 }
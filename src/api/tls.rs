@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf;
+
+/// TLS settings for talking to a self-hosted or staging API, shared by the
+/// HTTP client and the logs websocket.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    pub insecure: bool,
+    pub ca_cert: Option<Utf8PathBuf>,
+}
+
+impl TlsOptions {
+    /// --insecure only takes effect with MOLNETT_ALLOW_INSECURE=1 set, so it
+    /// can't end up in a production pipeline by accident.
+    pub fn new(insecure: bool, ca_cert: Option<Utf8PathBuf>) -> Result<Self> {
+        if insecure && std::env::var("MOLNETT_ALLOW_INSECURE").map_or(true, |v| v != "1") {
+            return Err(anyhow!(
+                "--insecure disables certificate verification, set MOLNETT_ALLOW_INSECURE=1 to allow it"
+            ));
+        }
+        Ok(Self { insecure, ca_cert })
+    }
+
+    pub fn is_default(&self) -> bool {
+        !self.insecure && self.ca_cert.is_none()
+    }
+
+    fn read_ca_cert(&self) -> Result<Option<Vec<u8>>> {
+        match &self.ca_cert {
+            Some(path) => Ok(Some(
+                std::fs::read(path).with_context(|| format!("Failed to read CA certificate {}", path))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub fn configure(
+        &self,
+        builder: reqwest::blocking::ClientBuilder,
+    ) -> Result<reqwest::blocking::ClientBuilder> {
+        let mut builder = builder.danger_accept_invalid_certs(self.insecure);
+        if let Some(pem) = self.read_ca_cert()? {
+            builder = builder.add_root_certificate(
+                reqwest::Certificate::from_pem(&pem).context("Invalid CA certificate")?,
+            );
+        }
+        Ok(builder)
+    }
+
+    pub fn tls_connector(&self) -> Result<native_tls::TlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder.danger_accept_invalid_certs(self.insecure);
+        if let Some(pem) = self.read_ca_cert()? {
+            builder.add_root_certificate(
+                native_tls::Certificate::from_pem(&pem).context("Invalid CA certificate")?,
+            );
+        }
+        Ok(builder.build()?)
+    }
+}
@@ -1,6 +1,7 @@
 use std::fmt::{Display, Formatter, Result};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tabled::Tabled;
 
 #[derive(Serialize, Deserialize, Debug, Tabled)]
@@ -28,6 +29,7 @@ pub struct ListServicesResponse {
 }
 
 #[derive(Serialize, Deserialize, Debug, Tabled, Clone, PartialEq)]
+#[serde(try_from = "ServiceSpec")]
 pub struct Service {
     pub name: String,
     pub image: String,
@@ -35,7 +37,166 @@ pub struct Service {
     #[serde(default, skip_serializing_if = "is_default")]
     pub env: DisplayOption<DisplayHashMap>,
     #[serde(default, skip_serializing_if = "is_default")]
-    pub secrets: DisplayOption<DisplayHashMap>
+    pub secrets: DisplayOption<DisplayHashMap>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub restart_policy: DisplayOption<RestartPolicy>,
+    /// Cron expression for services that run as scheduled jobs.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub schedule: DisplayOption<String>,
+    /// Replaces the image's ENTRYPOINT.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[tabled(skip)]
+    pub command: Vec<String>,
+    /// Replaces the image's CMD, i.e. the arguments passed to the entrypoint.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[tabled(skip)]
+    pub args: Vec<String>,
+}
+
+impl Service {
+    /// The service with every field that holds its default value unset, so
+    /// e.g. an empty `env` or an explicit `restart_policy: always` compares
+    /// equal to leaving them out.
+    pub fn normalized(&self) -> Service {
+        let mut service = self.clone();
+        if service.env.0.as_ref().is_some_and(|env| env.0.is_empty()) {
+            service.env = DisplayOption(None);
+        }
+        if service.secrets.0.as_ref().is_some_and(|secrets| secrets.0.is_empty()) {
+            service.secrets = DisplayOption(None);
+        }
+        if service.restart_policy.0 == Some(RestartPolicy::default()) {
+            service.restart_policy = DisplayOption(None);
+        }
+        if service.schedule.0.as_deref().is_some_and(|schedule| schedule.trim().is_empty()) {
+            service.schedule = DisplayOption(None);
+        }
+        service
+    }
+
+    /// SHA-256 of the normalized service as JSON with every map's keys
+    /// sorted, so two manifests describing the same service hash the same
+    /// regardless of the order they list fields and variables in.
+    pub fn content_hash(&self) -> anyhow::Result<String> {
+        let canonical = canonicalize(serde_json::to_value(self.normalized())?);
+        Ok(format!("{:x}", Sha256::digest(canonical.to_string())))
+    }
+}
+
+fn canonicalize(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries = map
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(entries.into_iter().collect())
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(canonicalize).collect())
+        }
+        value => value,
+    }
+}
+
+/// When the platform restarts a service's container after it exits.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Always,
+    OnFailure,
+    Never,
+}
+
+impl RestartPolicy {
+    pub const ALL: [RestartPolicy; 3] = [RestartPolicy::Always, RestartPolicy::OnFailure, RestartPolicy::Never];
+}
+
+impl Display for RestartPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            RestartPolicy::Always => write!(f, "always"),
+            RestartPolicy::OnFailure => write!(f, "on-failure"),
+            RestartPolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// Wire form of a `Service`. Values in `env` may either be literals or
+/// `{ secretRef: NAME }`, the latter are moved over to `secrets`.
+#[derive(Deserialize)]
+struct ServiceSpec {
+    name: String,
+    image: String,
+    container_port: u16,
+    #[serde(default)]
+    env: Option<IndexMap<String, EnvValue>>,
+    #[serde(default)]
+    secrets: DisplayOption<DisplayHashMap>,
+    #[serde(default)]
+    restart_policy: DisplayOption<RestartPolicy>,
+    #[serde(default)]
+    schedule: DisplayOption<String>,
+    #[serde(default)]
+    command: Vec<String>,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EnvValue {
+    String(String),
+    SecretRef {
+        #[serde(rename = "secretRef")]
+        secret_ref: String,
+    },
+}
+
+impl TryFrom<ServiceSpec> for Service {
+    type Error = String;
+
+    fn try_from(spec: ServiceSpec) -> std::result::Result<Self, Self::Error> {
+        let mut secrets = spec.secrets.0;
+        let env = match spec.env {
+            None => None,
+            Some(vars) => {
+                let mut env = IndexMap::new();
+                for (key, value) in vars {
+                    match value {
+                        EnvValue::String(value) => {
+                            env.insert(key, value);
+                        }
+                        EnvValue::SecretRef { secret_ref } => {
+                            let secrets = secrets.get_or_insert_with(Default::default);
+                            if secrets.0.contains_key(&key) {
+                                return Err(format!(
+                                    "{} is set both as a secretRef in env and in secrets",
+                                    key
+                                ));
+                            }
+                            secrets.0.insert(key, secret_ref);
+                        }
+                    }
+                }
+                Some(DisplayHashMap(env))
+            }
+        };
+
+        Ok(Service {
+            name: spec.name,
+            image: spec.image,
+            container_port: spec.container_port,
+            env: DisplayOption(env),
+            secrets: DisplayOption(secrets),
+            restart_policy: spec.restart_policy,
+            schedule: spec.schedule,
+            command: spec.command,
+            args: spec.args,
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -87,3 +248,75 @@ impl<T: Display> Display for DisplayOption<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(yaml: &str) -> Service {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const WEB: &str = "name: web\nimage: a:1\ncontainer_port: 80\n";
+
+    fn same_content(a: &Service, b: &Service) -> bool {
+        a.content_hash().unwrap() == b.content_hash().unwrap()
+    }
+
+    #[test]
+    fn default_valued_fields_hash_like_missing_ones() {
+        let web = service(WEB);
+        for extra in [
+            "env: {}",
+            "secrets: {}",
+            "restart_policy: always",
+            "schedule: ''",
+            "schedule: '  '",
+            "command: []\nargs: []",
+        ] {
+            let other = service(&format!("{}{}\n", WEB, extra));
+            assert!(same_content(&web, &other), "{}", extra);
+        }
+        assert_eq!(service(&format!("{}env: {{}}\n", WEB)).normalized(), web);
+    }
+
+    #[test]
+    fn changed_fields_hash_differently() {
+        let web = service(&format!("{}env:\n  A: '1'\n  B: '2'\n", WEB));
+        for changed in [
+            WEB.replace("a:1", "a:2"),
+            format!("{}env:\n  A: '1'\n", WEB),
+            format!("{}env:\n  A: '1'\n  B: '2'\nrestart_policy: never\n", WEB),
+            format!("{}env:\n  A: '1'\n  B: '2'\nargs: [serve]\n", WEB),
+        ] {
+            assert!(!same_content(&web, &service(&changed)), "{}", changed);
+        }
+    }
+
+    #[test]
+    fn content_hash_ignores_field_and_key_order() {
+        let web = service(&format!("{}env:\n  A: '1'\n  B: '2'\n", WEB));
+        let reordered = service("env:\n  B: '2'\n  A: '1'\ncontainer_port: 80\nimage: a:1\nname: web\n");
+        assert_eq!(web.content_hash().unwrap(), reordered.content_hash().unwrap());
+        assert_eq!(web.content_hash().unwrap().len(), 64);
+
+        let changed = service(&format!("{}env:\n  A: '1'\n  B: '3'\n", WEB));
+        assert_ne!(web.content_hash().unwrap(), changed.content_hash().unwrap());
+    }
+
+    #[test]
+    fn command_and_args_round_trip() {
+        let web = service(&format!("{}command: [/bin/server]\nargs: [--port, '80']\n", WEB));
+        assert_eq!(web.command, vec!["/bin/server"]);
+        assert_eq!(web.args, vec!["--port", "80"]);
+
+        let yaml = serde_yaml::to_string(&web).unwrap();
+        assert_eq!(service(&yaml), web);
+        let json = serde_json::to_string(&web).unwrap();
+        assert!(json.contains(r#""command":["/bin/server"],"args":["--port","80"]"#));
+        assert_eq!(serde_json::from_str::<Service>(&json).unwrap(), web);
+
+        let yaml = serde_yaml::to_string(&service(WEB)).unwrap();
+        assert!(!yaml.contains("command") && !yaml.contains("args"));
+    }
+}
@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Result};
+use std::net::TcpStream;
+use tungstenite::http::Uri;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{client_tls_with_config, connect, ClientRequestBuilder, Connector, WebSocket};
+
+use super::proxy::{tunnel, ProxyOptions};
+use super::tls::TlsOptions;
+
+/// Opens a websocket to `uri` with the same TLS and proxy settings as the
+/// HTTP client.
+pub fn connect_websocket(
+    uri: &Uri,
+    request: ClientRequestBuilder,
+    tls: &TlsOptions,
+    proxy: &ProxyOptions,
+) -> Result<WebSocket<MaybeTlsStream<TcpStream>>> {
+    let host = uri.host().ok_or_else(|| anyhow!("{} has no host", uri))?;
+    let secure = uri.scheme_str() == Some("wss");
+    let proxy_url = proxy.for_host(host, secure)?;
+
+    if tls.is_default() && proxy_url.is_none() {
+        let (socket, _) = connect(request)?;
+        return Ok(socket);
+    }
+
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+    let stream = match &proxy_url {
+        Some(proxy_url) => tunnel(proxy_url, host, port)?,
+        None => TcpStream::connect((host, port))?,
+    };
+    let connector = Connector::NativeTls(tls.tls_connector()?);
+    let (socket, _) = client_tls_with_config(request, stream, None, Some(connector))?;
+    Ok(socket)
+}
@@ -13,7 +13,7 @@ use tiny_http::{Response, Server};
 
 use crate::config::user::Token;
 
-use super::CommandBase;
+use super::{docker_not_found, CommandBase};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -116,14 +116,7 @@ pub struct Docker {}
 
 impl Docker {
     pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
-        let token = base.user_config.get_token().ok_or_else(|| {
-            anyhow!("Could not get Molnett token. Please run molnctl auth login.")
-        })?;
-
-        if base.user_config.is_token_expired() {
-            println!("Token expired. Please run molnctl auth login.");
-            return Ok(());
-        }
+        let token = base.get_token()?;
 
         let mut command = Command::new("docker")
             .arg("login")
@@ -132,7 +125,9 @@ impl Docker {
             .arg("--password-stdin")
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .spawn()?;
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(docker_not_found)?;
 
         if let Some(mut stdin) = command.stdin.take() {
             stdin.write_all(token.as_bytes())?;
@@ -141,10 +136,13 @@ impl Docker {
         let output = command.wait_with_output()?;
 
         if !output.status.success() {
-            println!("{}", String::from_utf8_lossy(&output.stderr));
-        } else {
-            println!("{}", String::from_utf8_lossy(&output.stdout));
+            return Err(anyhow!(
+                "docker login exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
         }
+        println!("{}", String::from_utf8_lossy(&output.stdout));
         Ok(())
     }
 }
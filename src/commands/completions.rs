@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use clap_complete::{generate, Shell};
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+use super::CommandError;
+
+#[derive(Debug, Parser)]
+pub struct Completions {
+    #[arg(value_enum, help = "Shell to generate completions for. Default is the shell in $SHELL")]
+    shell: Option<Shell>,
+    #[arg(long, help = "Write the completions to the shell's completion directory instead of stdout")]
+    install: bool,
+}
+
+impl Completions {
+    /// Takes the clap command of the whole CLI, which only `main` can build.
+    pub fn execute(&self, mut cmd: clap::Command) -> Result<()> {
+        let shell = match self.shell.or_else(Shell::from_env) {
+            Some(shell) => shell,
+            None => {
+                return Err(CommandError::Usage(
+                    "Could not detect your shell from $SHELL, please pass it explicitly".to_string(),
+                )
+                .into())
+            }
+        };
+        let name = cmd.get_name().to_string();
+
+        if !self.install {
+            generate(shell, &mut cmd, name, &mut io::stdout());
+            return Ok(());
+        }
+
+        let path = install_path(shell, &name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        generate(shell, &mut cmd, name, &mut File::create(&path)?);
+        println!("Wrote {} completions to {}", shell, path.display());
+        if shell == Shell::Zsh {
+            println!(
+                "Make sure {} is in your fpath and compinit is run in ~/.zshrc",
+                path.parent().unwrap_or(&path).display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The conventional per-user completion location of each shell.
+fn install_path(shell: Shell, name: &str) -> Result<PathBuf> {
+    let path = match shell {
+        Shell::Bash => dirs_next::data_dir()
+            .map(|dir| dir.join("bash-completion").join("completions").join(name)),
+        Shell::Zsh => dirs_next::home_dir().map(|dir| dir.join(".zfunc").join(format!("_{}", name))),
+        Shell::Fish => dirs_next::config_dir()
+            .map(|dir| dir.join("fish").join("completions").join(format!("{}.fish", name))),
+        _ => {
+            return Err(CommandError::Usage(format!(
+                "--install is not supported for {}, redirect the output to your completion directory instead",
+                shell
+            ))
+            .into())
+        }
+    };
+    path.ok_or_else(|| anyhow!("Could not find the home directory to install completions in"))
+}
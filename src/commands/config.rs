@@ -0,0 +1,150 @@
+use anyhow::Result;
+use camino::Utf8Path;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Parser, Subcommand};
+
+use super::CommandBase;
+use crate::config::user::UserConfig;
+use crate::Cli;
+
+#[derive(Debug, Parser)]
+#[command(
+    author,
+    version,
+    about,
+    long_about,
+    subcommand_required = true,
+    arg_required_else_help = true
+)]
+pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+impl Config {
+    pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
+        match &self.command {
+            Some(Commands::Migrate(migrate)) => migrate.execute(base),
+            Some(Commands::Reset(reset)) => reset.execute(base.user_config().get_path()),
+            Some(Commands::Explain(_)) | None => Ok(()),
+        }
+    }
+
+    /// `config reset` has to work when the current config can't be loaded,
+    /// so it is run before a `CommandBase` exists.
+    pub fn reset(&self) -> Option<&Reset> {
+        match &self.command {
+            Some(Commands::Reset(reset)) => Some(reset),
+            _ => None,
+        }
+    }
+
+    /// `config explain` needs to know where each global flag's value came
+    /// from, which only the parsed `ArgMatches` can tell.
+    pub fn explain(&self) -> Option<&Explain> {
+        match &self.command {
+            Some(Commands::Explain(explain)) => Some(explain),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Update the config file to the current format
+    Migrate(Migrate),
+    /// Back up the config file and replace it with a fresh default
+    Reset(Reset),
+    /// Show the settings in effect and where each one comes from
+    Explain(Explain),
+}
+
+#[derive(Debug, Parser)]
+pub struct Migrate {}
+
+impl Migrate {
+    pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
+        let changes = base.user_config_mut().migrate()?;
+        let path = base.user_config().get_path();
+
+        if changes.is_empty() {
+            println!("{} is up to date", path);
+            return Ok(());
+        }
+
+        println!("Migrated {}:", path);
+        for change in changes {
+            println!("  {}", change);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Reset {}
+
+impl Reset {
+    pub fn execute(&self, path: &Utf8Path) -> Result<()> {
+        match UserConfig::reset(path)? {
+            Some(backup) => println!("Moved the old config to {} and wrote a fresh {}", backup, path),
+            None => println!("Wrote a fresh {}", path),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Explain {}
+
+impl Explain {
+    pub fn execute(&self, cli: &Cli, matches: &ArgMatches, config: &UserConfig) -> Result<()> {
+        let config_source = flag_source(matches, "config", "--config", "MOLNETT_CONFIG").unwrap_or("default");
+        println!("config file:  {} ({})", config.get_path(), config_source);
+
+        let context_source = flag_source(matches, "context", "--context", "MOLNETT_CONTEXT")
+            .unwrap_or(if config.get_current_context().is_some() { "config file" } else { "default" });
+        println!("context:      {} ({})", config.get_context(), context_source);
+
+        let url_source = flag_source(matches, "url", "--url", "MOLNETT_API_URL")
+            .unwrap_or(if config.is_default_url() { "default" } else { "config file" });
+        println!("url:          {} ({})", config.get_url(), url_source);
+
+        match (&cli.org, config.get_default_org()) {
+            (Some(org), _) => println!(
+                "org:          {} ({})",
+                org,
+                flag_source(matches, "org", "--org", "MOLNETT_ORG").unwrap_or("--org")
+            ),
+            (None, Some(org)) => println!("org:          {} (config file)", org),
+            (None, None) => println!("org:          not set, use --org or `molnctl orgs switch`"),
+        }
+
+        match config.get_default_environment() {
+            Some(env) => println!("environment:  {} (config file, used when the manifest has none)", env),
+            None => println!("environment:  not set, taken from the manifest or --env"),
+        }
+
+        match (config.get_token(), config.get_token_expiry()) {
+            (None, _) => println!("token:        not logged in"),
+            (Some(_), _) if config.is_token_expired() => println!("token:        expired (config file)"),
+            (Some(_), Some(expiry)) => println!("token:        valid until {} (config file)", expiry),
+            (Some(_), None) => println!("token:        present (config file)"),
+        }
+        Ok(())
+    }
+}
+
+/// Describes where a global flag got its value, or `None` when it wasn't
+/// given at all.
+fn flag_source(
+    matches: &ArgMatches,
+    id: &str,
+    flag: &'static str,
+    env: &'static str,
+) -> Option<&'static str> {
+    match matches.value_source(id)? {
+        ValueSource::CommandLine => Some(flag),
+        ValueSource::EnvVariable => Some(env),
+        _ => None,
+    }
+}
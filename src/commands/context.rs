@@ -0,0 +1,149 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use tabled::{Table, Tabled};
+
+use super::{CommandBase, CommandError};
+
+#[derive(Debug, Parser)]
+#[command(
+    author,
+    version,
+    about,
+    long_about,
+    subcommand_required = true,
+    arg_required_else_help = true
+)]
+pub struct Context {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+impl Context {
+    pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
+        match &self.command {
+            Some(Commands::List(list)) => list.execute(base),
+            Some(Commands::Current(current)) => current.execute(base),
+            Some(Commands::Create(create)) => create.execute(base),
+            Some(Commands::Use(use_)) => use_.execute(base),
+            Some(Commands::Delete(delete)) => delete.execute(base),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// List contexts
+    List(List),
+    /// Print the context in use
+    Current(Current),
+    /// Create a context for another url or account
+    Create(Create),
+    /// Switch the current context
+    Use(Use),
+    /// Delete a context
+    Delete(Delete),
+}
+
+#[derive(Tabled)]
+struct ContextRow<'a> {
+    current: &'static str,
+    name: &'a str,
+    url: &'a str,
+    org: &'a str,
+}
+
+#[derive(Debug, Parser)]
+pub struct List {}
+
+impl List {
+    pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        let config = base.user_config();
+        let rows = config.contexts().map(|(name, context)| ContextRow {
+            current: if name == config.get_context() { "*" } else { "" },
+            name,
+            url: context.url(),
+            org: context.default_org().unwrap_or(""),
+        });
+        println!("{}", Table::new(rows));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Current {}
+
+impl Current {
+    pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        println!("{}", base.user_config().get_context());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Create {
+    #[arg(help = "Name of the context")]
+    name: String,
+    #[arg(long = "api-url", help = "Url of the Molnett API for this context", default_value = "https://api.molnett.org")]
+    api_url: String,
+    #[arg(long = "use", help = "Switch to the new context")]
+    use_: bool,
+}
+
+impl Create {
+    pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
+        // The config loader lowercases keys, so only lowercase names survive
+        // a round trip through the config file.
+        let valid = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid {
+            return Err(CommandError::Usage(format!(
+                "Invalid context name '{}', use lowercase letters, digits, '-' and '_'",
+                self.name
+            ))
+            .into());
+        }
+
+        let config = base.user_config_mut();
+        config.create_context(&self.name, self.api_url.trim_end_matches('/').to_string())?;
+        if self.use_ {
+            config.use_context(&self.name)?;
+            println!("Created and switched to context {}", self.name);
+        } else {
+            println!("Created context {}", self.name);
+        }
+        println!("Log in with `molnctl --context {} auth login`", self.name);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Use {
+    #[arg(help = "Name of the context")]
+    name: String,
+}
+
+impl Use {
+    pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
+        base.user_config_mut().use_context(&self.name)?;
+        println!("Switched to context {}", self.name);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Delete {
+    #[arg(help = "Name of the context")]
+    name: String,
+}
+
+impl Delete {
+    pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
+        base.user_config_mut().delete_context(&self.name)?;
+        println!("Deleted context {}", self.name);
+        Ok(())
+    }
+}
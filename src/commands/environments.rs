@@ -1,8 +1,10 @@
-use super::CommandBase;
-use anyhow::{anyhow, Result};
+use super::{confirm_or_cancel, prompt_error, CommandBase, ConfirmArgs, OutputFormat};
+use crate::api::ApiError;
+use anyhow::Result;
 use clap::{Parser, Subcommand};
 use dialoguer::FuzzySelect;
-use tabled::Table;
+use serde::Serialize;
+use tabled::{Table, Tabled};
 
 #[derive(Debug, Parser)]
 #[command(
@@ -24,7 +26,9 @@ impl Environments {
         match &self.command {
             Some(Commands::Create(create)) => create.execute(base),
             Some(Commands::List(list)) => list.execute(base),
+            Some(Commands::Get(get)) => get.execute(base),
             Some(Commands::Delete(delete)) => delete.execute(base),
+            Some(Commands::Switch(switch)) => switch.execute(base),
             None => Ok(()),
         }
     }
@@ -38,8 +42,12 @@ pub enum Commands {
     /// List environments
     #[command()]
     List(List),
+    /// Show a single environment
+    Get(Get),
     /// Delete an environment
     Delete(Delete),
+    /// Switch the default environment for deploys without an environment
+    Switch(Switch),
 }
 
 #[derive(Debug, Parser)]
@@ -54,10 +62,7 @@ pub struct Create {
 impl Create {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
         let response = base
             .api_client()
@@ -76,10 +81,7 @@ pub struct List {}
 impl List {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
         let response = base.api_client().get_environments(token, &org_name)?;
 
@@ -89,30 +91,59 @@ impl List {
     }
 }
 
+#[derive(Debug, Parser)]
+pub struct Get {
+    #[arg(help = "Name of the environment")]
+    name: String,
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    output: OutputFormat,
+}
+
+#[derive(Serialize, Tabled)]
+struct EnvironmentDetails<'a> {
+    name: &'a str,
+    org: &'a str,
+}
+
+impl Get {
+    pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        let org_name = base.get_org()?;
+        let token = base.get_token()?;
+
+        let envs = base.api_client().get_environments(token, &org_name)?;
+        if !envs.contains(&self.name) {
+            return Err(ApiError::NotFound(format!("Environment {} does not exist", self.name)).into());
+        }
+
+        let details = EnvironmentDetails {
+            name: &self.name,
+            org: &org_name,
+        };
+        match self.output {
+            OutputFormat::Table => println!("{}", Table::new([details])),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&details)?),
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct Delete {
     #[arg(help = "Name of the environment")]
     name: String,
-    #[arg(long, help = "Skip confirmation", default_missing_value("true"), default_value("false"), num_args(0..=1), require_equals(true))]
-    no_confirm: Option<bool>,
+    #[command(flatten)]
+    confirm: ConfirmArgs,
 }
 
 impl Delete {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
-        if let Some(false) = self.no_confirm {
+        if self.confirm.needs_confirmation() {
             let prompt = format!("Org: {}, Environment: {}. Are you sure you want to delete this environment and everything in it?", org_name, self.name);
-            FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                .with_prompt(prompt)
-                .items(&["no", "yes"])
-                .default(0)
-                .interact()
-                .unwrap();
+            confirm_or_cancel(&prompt)?;
         }
 
         base.api_client()
@@ -122,3 +153,36 @@ impl Delete {
         Ok(())
     }
 }
+
+#[derive(Debug, Parser)]
+pub struct Switch {
+    #[arg(help = "Name of the environment to switch to")]
+    name: Option<String>,
+}
+
+impl Switch {
+    pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
+        let org_name = base.get_org()?;
+        let envs = base
+            .api_client()
+            .get_environments(base.get_token()?, &org_name)?;
+
+        let env_name = match &self.name {
+            Some(name) if envs.contains(name) => name.clone(),
+            Some(name) => {
+                return Err(ApiError::NotFound(format!("Environment {} does not exist", name)).into())
+            }
+            None => {
+                let selection = FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                    .with_prompt("Please select your environment: ")
+                    .items(&envs[..])
+                    .interact()
+                    .map_err(prompt_error)?;
+                envs[selection].clone()
+            }
+        };
+
+        base.user_config_mut().write_default_environment(env_name)?;
+        Ok(())
+    }
+}
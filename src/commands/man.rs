@@ -0,0 +1,35 @@
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use clap_mangen::Man;
+use std::fs::{self, File};
+
+#[derive(Debug, Parser)]
+pub struct GenerateMan {
+    #[arg(help = "Directory to write the man pages to")]
+    outdir: Utf8PathBuf,
+}
+
+impl GenerateMan {
+    /// Writes one page per command, named like `molnctl-services-deploy.1`.
+    pub fn execute(&self, cmd: clap::Command) -> Result<()> {
+        fs::create_dir_all(&self.outdir)?;
+        let cmd = cmd.disable_help_subcommand(true);
+        let name = cmd.get_name().to_string();
+        write_pages(&self.outdir, cmd, &name, &name)
+    }
+}
+
+fn write_pages(outdir: &Utf8Path, cmd: clap::Command, name: &str, bin_name: &str) -> Result<()> {
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let sub_name = format!("{}-{}", name, sub.get_name());
+        let sub_bin_name = format!("{} {}", bin_name, sub.get_name());
+        let sub = sub.clone().name(sub_name.clone()).bin_name(sub_bin_name.clone());
+        write_pages(outdir, sub, &sub_name, &sub_bin_name)?;
+    }
+
+    let path = outdir.join(format!("{}.1", name));
+    Man::new(cmd).render(&mut File::create(&path)?)?;
+    println!("Wrote {}", path);
+    Ok(())
+}
@@ -1,32 +1,212 @@
 use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use dialoguer::{FuzzySelect, Input};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use thiserror::Error;
 
 use crate::{
-    api::APIClient,
+    api::{proxy::ProxyOptions, tls::TlsOptions, ApiError, MolnettApi},
     config::user::UserConfig,
 };
 
 pub mod auth;
+pub mod completions;
+pub mod config;
+pub mod context;
 pub mod environments;
+pub mod man;
 pub mod orgs;
 pub mod secrets;
 pub mod services;
 
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("No token found. Please login first.")]
+    NotLoggedIn,
+    #[error("Your session has expired.")]
+    SessionExpired,
+    #[error("{0}")]
+    Usage(String),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Flags that skip the confirmation prompt of destructive commands.
+#[derive(Clone, Debug, Default, Args)]
+pub struct ConfirmArgs {
+    #[arg(short, long, help = "Skip confirmation")]
+    yes: bool,
+    /// Kept for existing scripts. A bare `--no-confirm` means `--no-confirm=true`;
+    /// the value must be attached with `=` so a following positional argument
+    /// is never taken as its value.
+    #[arg(long, help = "Deprecated, use --yes", default_missing_value("true"), num_args(0..=1), require_equals(true))]
+    no_confirm: Option<bool>,
+}
+
+impl ConfirmArgs {
+    /// Whether the user still needs to be asked before going ahead.
+    pub fn needs_confirmation(&self) -> bool {
+        !self.yes && !matches!(self.no_confirm, Some(true))
+    }
+}
+
+/// Returned when the user interrupts a prompt with Ctrl-C or answers no to
+/// going ahead.
+#[derive(Error, Debug)]
+#[error("cancelled")]
+pub struct Cancelled;
+
+/// Maps the error of an interactive prompt, turning an interrupted read into
+/// `Cancelled` so it can be told apart from real I/O failures.
+pub fn prompt_error(err: std::io::Error) -> anyhow::Error {
+    if err.kind() == std::io::ErrorKind::Interrupted {
+        Cancelled.into()
+    } else {
+        err.into()
+    }
+}
+
+/// Maps the error of spawning `docker`, explaining a missing binary instead
+/// of reporting a bare "No such file or directory".
+pub fn docker_not_found(err: std::io::Error) -> anyhow::Error {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        anyhow!("Docker CLI not found. Please install Docker or make sure it is on your PATH.")
+    } else {
+        err.into()
+    }
+}
+
+/// Asks a yes or no question, defaulting to no, and fails with `Cancelled`
+/// unless the user picks yes.
+pub fn confirm_or_cancel(prompt: &str) -> Result<()> {
+    let selection = FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(&CONFIRM_ITEMS)
+        .default(0)
+        .interact()
+        .map_err(prompt_error)?;
+    confirmed(selection)
+}
+
+const CONFIRM_ITEMS: [&str; 2] = ["no", "yes"];
+
+fn confirmed(selection: usize) -> Result<()> {
+    match CONFIRM_ITEMS.get(selection) {
+        Some(&"yes") => Ok(()),
+        _ => Err(Cancelled.into()),
+    }
+}
+
+/// Asks the user to type `expected` to confirm a destructive operation.
+pub fn confirm_by_typing(expected: &str) -> Result<bool> {
+    let input: String = Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
+        .with_prompt(format!("Type {} to confirm", expected))
+        .allow_empty(true)
+        .interact_text()
+        .map_err(prompt_error)?;
+    Ok(input == expected)
+}
+
+const BULK_RATE_LIMIT_RETRIES: usize = 5;
+const BULK_RATE_LIMIT_DEFAULT_WAIT: Duration = Duration::from_secs(1);
+
+/// Runs `op` for every item on at most `concurrency` threads. Calls that get
+/// rate limited are retried after the delay the API asks for. Returns the
+/// items that failed along with their errors, in input order.
+pub fn run_bulk<T, F>(items: &[T], concurrency: usize, op: F) -> Vec<(&T, anyhow::Error)>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<()> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let failures = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                if let Err(err) = run_with_rate_limit_retry(|| op(item)) {
+                    failures.lock().unwrap().push((index, err));
+                }
+            });
+        }
+    });
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by_key(|(index, _)| *index);
+    failures
+        .into_iter()
+        .map(|(index, err)| (&items[index], err))
+        .collect()
+}
+
+fn run_with_rate_limit_retry(op: impl Fn() -> Result<()>) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let err = match op() {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        let wait = match err.downcast_ref::<ApiError>() {
+            Some(ApiError::TooManyRequests(wait)) if attempt < BULK_RATE_LIMIT_RETRIES => {
+                wait.unwrap_or(BULK_RATE_LIMIT_DEFAULT_WAIT)
+            }
+            _ => return Err(err),
+        };
+        attempt += 1;
+        std::thread::sleep(wait);
+    }
+}
+
 pub struct CommandBase<'a> {
     user_config: &'a mut UserConfig,
+    api_client: Box<dyn MolnettApi>,
     org_arg: Option<String>,
+    // Org ids don't change during a command, so each is looked up only once.
+    org_ids: Mutex<HashMap<String, String>>,
+    tls: TlsOptions,
+    proxy: ProxyOptions,
 }
 
 impl CommandBase<'_> {
-    pub fn new(user_config: &mut UserConfig, org_arg: Option<String>) -> CommandBase {
+    pub fn new(
+        user_config: &mut UserConfig,
+        api_client: Box<dyn MolnettApi>,
+        org_arg: Option<String>,
+        tls: TlsOptions,
+        proxy: ProxyOptions,
+    ) -> CommandBase<'_> {
         CommandBase {
             user_config,
+            api_client,
             org_arg,
+            org_ids: Mutex::new(HashMap::new()),
+            tls,
+            proxy,
         }
     }
 
-    pub fn api_client(&self) -> APIClient {
-        let url = self.user_config.get_url();
-        APIClient::new(url)
+    pub fn tls(&self) -> &TlsOptions {
+        &self.tls
+    }
+
+    pub fn proxy(&self) -> &ProxyOptions {
+        &self.proxy
+    }
+
+    pub fn api_client(&self) -> &dyn MolnettApi {
+        self.api_client.as_ref()
     }
 
     pub fn user_config(&self) -> &UserConfig {
@@ -37,15 +217,100 @@ impl CommandBase<'_> {
         self.user_config
     }
 
+    /// The token to authenticate with. Fails up front when it has expired,
+    /// rather than letting the API answer with a bare 401.
+    pub fn get_token(&self) -> Result<&str> {
+        let token = self
+            .user_config
+            .get_token()
+            .ok_or(CommandError::NotLoggedIn)?;
+        if self.user_config.is_token_expired() {
+            return Err(CommandError::SessionExpired.into());
+        }
+        Ok(token)
+    }
+
     pub fn get_org(&self) -> Result<String> {
         let org_name = if self.org_arg.is_some() {
             self.org_arg.clone().unwrap()
         } else {
             match self.user_config.get_default_org() {
                 Some(cfg) => cfg.to_string(),
-                None => return Err(anyhow!("Either set a default org in the config or provide one via --org"))
+                None => {
+                    return Err(CommandError::Usage(
+                        "Either set a default org in the config or provide one via --org"
+                            .to_string(),
+                    )
+                    .into())
+                }
             }
         };
         Ok(org_name)
     }
+
+    pub fn get_org_id(&self) -> Result<String> {
+        let org_name = self.get_org()?;
+        if let Some(id) = self.org_ids.lock().unwrap().get(&org_name) {
+            return Ok(id.clone());
+        }
+
+        let id = self.api_client().get_org(self.get_token()?, &org_name)?.id;
+        self.org_ids.lock().unwrap().insert(org_name, id.clone());
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct Delete {
+        name: Option<String>,
+        #[command(flatten)]
+        confirm: ConfirmArgs,
+    }
+
+    fn parse(args: &[&str]) -> Delete {
+        Delete::try_parse_from(std::iter::once("delete").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn confirmation_flags() {
+        assert!(parse(&[]).confirm.needs_confirmation());
+        assert!(!parse(&["--yes"]).confirm.needs_confirmation());
+        assert!(!parse(&["-y"]).confirm.needs_confirmation());
+        assert!(!parse(&["--no-confirm"]).confirm.needs_confirmation());
+        assert!(!parse(&["--no-confirm=true"]).confirm.needs_confirmation());
+        assert!(parse(&["--no-confirm=false"]).confirm.needs_confirmation());
+        assert!(!parse(&["--no-confirm=false", "--yes"]).confirm.needs_confirmation());
+    }
+
+    #[test]
+    fn bare_no_confirm_leaves_the_next_argument_alone() {
+        let delete = parse(&["--no-confirm", "web"]);
+        assert_eq!(delete.name.as_deref(), Some("web"));
+        assert!(!delete.confirm.needs_confirmation());
+        assert!(Delete::try_parse_from(["delete", "--no-confirm", "maybe"]).is_ok());
+        assert!(Delete::try_parse_from(["delete", "--no-confirm=maybe"]).is_err());
+    }
+
+    #[test]
+    fn only_yes_confirms() {
+        assert!(confirmed(1).is_ok());
+        assert!(confirmed(0).unwrap_err().is::<Cancelled>());
+        assert!(confirmed(2).unwrap_err().is::<Cancelled>());
+    }
+
+    #[test]
+    fn missing_docker_is_explained() {
+        let err = std::process::Command::new("molnctl-test-no-such-binary").spawn().unwrap_err();
+        assert_eq!(
+            docker_not_found(err).to_string(),
+            "Docker CLI not found. Please install Docker or make sure it is on your PATH."
+        );
+        let err = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        assert_eq!(docker_not_found(err).to_string(), "denied");
+    }
 }
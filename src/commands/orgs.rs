@@ -1,9 +1,10 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use clap::{Parser, Subcommand};
 use dialoguer::{FuzzySelect, Input};
 use tabled::Table;
 
-use super::CommandBase;
+use super::{prompt_error, CommandBase, OutputFormat};
+use crate::api::ApiError;
 
 #[derive(Parser)]
 #[derive(Debug)]
@@ -23,6 +24,7 @@ impl Orgs {
     pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
         match &self.command {
             Some(Commands::List(list)) => list.execute(&base),
+            Some(Commands::Get(get)) => get.execute(base),
             Some(Commands::Create(create)) => create.execute(&base),
             Some(Commands::Switch(switch)) => switch.execute(base),
             None => Ok(()),
@@ -35,6 +37,8 @@ impl Orgs {
 pub enum Commands {
     /// List your orgs
     List(List),
+    /// Show a single org
+    Get(Get),
     /// Create a new org
     Create(Create),
     /// Switch default org for all commands
@@ -47,10 +51,7 @@ pub struct List {}
 
 impl List {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
         let response = base.api_client().get_organizations(token)?;
 
@@ -61,6 +62,40 @@ impl List {
     }
 }
 
+#[derive(Parser)]
+#[derive(Debug)]
+pub struct Get {
+    #[arg(help = "Name of the org, defaults to the current org")]
+    name: Option<String>,
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    output: OutputFormat,
+    #[arg(long, help = "Only print the org id")]
+    id_only: bool,
+}
+
+impl Get {
+    pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        let token = base.get_token()?;
+        let org_name = match &self.name {
+            Some(name) => name.clone(),
+            None => base.get_org()?,
+        };
+
+        let org = base.api_client().get_org(token, &org_name)?;
+
+        if self.id_only {
+            println!("{}", org.id);
+            return Ok(());
+        }
+        match self.output {
+            OutputFormat::Table => println!("{}", Table::new([org])),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&org)?),
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Parser)]
 #[derive(Debug)]
 pub struct Create {
@@ -73,14 +108,11 @@ pub struct Create {
 
 impl Create {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
         let plan = CreatePlan::builder()
-            .name(self.name.as_deref())
-            .billing_email(self.billing_email.as_deref())
+            .name(self.name.as_deref())?
+            .billing_email(self.billing_email.as_deref())?
             .build()?;
 
         let response = base.api_client().create_organization(
@@ -114,36 +146,36 @@ impl CreatePlanBuilder {
         }
     }
 
-    pub fn name(mut self, name: Option<&str>) -> Self {
+    pub fn name(mut self, name: Option<&str>) -> Result<Self> {
         if name.is_none() {
             let prompt = "Please enter a name for your organization: ";
             let input: String = Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
                 .with_prompt(prompt)
                 .interact_text()
-                .unwrap();
+                .map_err(prompt_error)?;
 
             self.name = input;
-            return self;
+            Ok(self)
         } else {
             self.name = name.unwrap().to_string();
-            return self;
+            Ok(self)
         }
     }
 
-    pub fn billing_email(mut self, billing_email: Option<&str>) -> Self {
+    pub fn billing_email(mut self, billing_email: Option<&str>) -> Result<Self> {
         if billing_email.is_some() {
             self.billing_email = billing_email.unwrap().to_string();
-            return self;
+            return Ok(self);
         }
 
         let prompt = "Please enter the billing email for your organization: ";
         let input: String = Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
             .with_prompt(prompt)
             .interact_text()
-            .unwrap();
+            .map_err(prompt_error)?;
 
         self.billing_email = input;
-        return self;
+        Ok(self)
     }
 
     fn verify(&self) -> Result<()> {
@@ -176,7 +208,7 @@ impl Switch {
     pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
         let orgs = base
             .api_client()
-            .get_organizations(base.user_config().get_token().unwrap())?;
+            .get_organizations(base.get_token()?)?;
         let org_names = orgs
             .organizations
             .iter()
@@ -188,14 +220,14 @@ impl Switch {
             if org_names.contains(&arg_org.as_str()) {
                 arg_org
             } else {
-                return Err(anyhow!("organization {} does not exist or you do not have access to it", arg_org))
+                return Err(ApiError::NotFound(format!("organization {} does not exist or you do not have access to it", arg_org)).into())
             }
         } else {
             let selection = FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
                 .with_prompt("Please select your organization: ")
                 .items(&org_names[..])
                 .interact()
-                .unwrap();
+                .map_err(prompt_error)?;
             org_names[selection].to_string()
         };
 
@@ -1,7 +1,8 @@
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
-use dialoguer::{FuzzySelect, Input};
-use super::CommandBase;
+use dialoguer::Password;
+use crate::util::Masked;
+use super::{confirm_by_typing, confirm_or_cancel, prompt_error, run_bulk, Cancelled, CommandBase, CommandError, ConfirmArgs};
 use std::io::{self, BufRead};
 use tabled::Table;
 
@@ -23,6 +24,7 @@ impl Secrets {
     pub fn execute(&self, base: &mut CommandBase) -> Result<()> {
         match &self.command {
             Some(Commands::Create(create)) => create.execute(base),
+            Some(Commands::Set(set)) => set.execute(base),
             Some(Commands::List(list)) => list.execute(base),
             Some(Commands::Delete(delete)) => delete.execute(base),
             None => Ok(())
@@ -35,6 +37,9 @@ pub enum Commands {
     /// Create a secret
     #[command(arg_required_else_help = true)]
     Create(Create),
+    /// Create or update secrets from KEY=VALUE pairs
+    #[command(arg_required_else_help = true)]
+    Set(Set),
     /// List secrets
     List(List),
     /// Delete a secret
@@ -54,18 +59,15 @@ pub struct Create {
 impl Create {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
         let value: String = if let Some(true) = self.stdin {
             self.read_stdin()?
         } else {
-            Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                .with_prompt("Secret value:")
-                .interact_text()
-                .expect("Failed to get user input")
+            Password::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                .with_prompt("Secret value")
+                .interact()
+                .map_err(prompt_error)?
         };
 
         base.api_client().create_secret(
@@ -102,6 +104,50 @@ impl Create {
     }
 }
 
+#[derive(Debug, Parser)]
+pub struct Set {
+    #[arg(help = "Secrets to set, as KEY=VALUE", value_name = "KEY=VALUE", required = true)]
+    pairs: Vec<String>,
+    #[arg(long, help = "Environment to set the secrets in")]
+    env: String,
+}
+
+impl Set {
+    pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        let org_name = base.get_org()?;
+        let token = base.get_token()?;
+
+        let pairs = self
+            .pairs
+            .iter()
+            .map(|pair| parse_pair(pair))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut failed = 0;
+        for (name, value) in pairs {
+            match base.api_client().create_secret(token, &org_name, &self.env, name, value) {
+                Ok(()) => println!("Secret {} set", name),
+                Err(err) => {
+                    eprintln!("Failed to set secret {}: {}", name, err);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(anyhow!("Failed to set {} of {} secrets", failed, self.pairs.len()));
+        }
+        Ok(())
+    }
+}
+
+fn parse_pair(pair: &str) -> Result<(&str, &str)> {
+    match pair.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name, value)),
+        _ => Err(CommandError::Usage(format!("Invalid secret '{}', expected KEY=VALUE", Masked::pair(pair))).into()),
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct List {
     #[arg(long, help = "Environment to list the secrets of")]
@@ -111,10 +157,7 @@ pub struct List {
 impl List {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
         let response = base.api_client().get_secrets(
             token,
@@ -131,40 +174,106 @@ impl List {
 
 #[derive(Debug, Parser)]
 pub struct Delete {
-    #[arg(help = "Name of the secret")]
-    name: String,
+    #[arg(help = "Name of the secret", required_unless_present = "all", conflicts_with = "all")]
+    name: Option<String>,
+    #[arg(long, help = "Delete every secret in the environment")]
+    all: bool,
+    #[arg(long, help = "Only delete secrets whose names start with this prefix", requires = "all")]
+    prefix: Option<String>,
+    #[arg(long, help = "Number of secrets to delete in parallel with --all", default_value_t = 4, requires = "all")]
+    concurrency: usize,
     #[arg(long, help = "Environment the secret is in")]
     env: String,
-    #[arg(long, help = "Skip confirmation", default_missing_value("true"), default_value("false"), num_args(0..=1), require_equals(true))]
-    no_confirm: Option<bool>,
+    #[command(flatten)]
+    confirm: ConfirmArgs,
 }
 
 impl Delete {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        let name = match &self.name {
+            Some(name) => name,
+            None => return self.delete_all(base),
+        };
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
-
-        if let Some(false) = self.no_confirm {
-            let prompt = format!("Org: {}, Environment: {}, Secret: {}. Are you sure you want to delete this secret?", org_name, self.env, self.name);
-            FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                .with_prompt(prompt)
-                .items(&["no", "yes"])
-                .default(0)
-                .interact()
-                .unwrap();
+        let token = base.get_token()?;
+
+        if self.confirm.needs_confirmation() {
+            let prompt = format!("Org: {}, Environment: {}, Secret: {}. Are you sure you want to delete this secret?", org_name, self.env, name);
+            confirm_or_cancel(&prompt)?;
         }
 
         base.api_client().delete_secret(
             token,
             &org_name,
             &self.env,
-            &self.name
+            name
         )?;
 
-        println!("Secret {} deleted", self.name);
+        println!("Secret {} deleted", name);
         Ok(())
     }
+
+    fn delete_all(&self, base: &CommandBase) -> Result<()> {
+        let org_name = base.get_org()?;
+        let token = base.get_token()?;
+
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        let names = base
+            .api_client()
+            .get_secrets(token, &org_name, &self.env)?
+            .secrets
+            .into_iter()
+            .map(|secret| secret.name)
+            .filter(|name| name.starts_with(prefix))
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            println!("No matching secrets in environment {}", self.env);
+            return Ok(());
+        }
+
+        if self.confirm.needs_confirmation() {
+            println!("The following secrets will be deleted from environment {}:", self.env);
+            for name in &names {
+                println!("  {}", name);
+            }
+            if !confirm_by_typing(&self.env)? {
+                return Err(Cancelled.into());
+            }
+        }
+
+        let failures = run_bulk(&names, self.concurrency, |name| {
+            base.api_client()
+                .delete_secret(token, &org_name, &self.env, name)?;
+            println!("Secret {} deleted", name);
+            Ok(())
+        });
+
+        if !failures.is_empty() {
+            for (name, err) in &failures {
+                eprintln!("Failed to delete secret {}: {}", name, err);
+            }
+            return Err(anyhow!("Failed to delete {} of {} secrets", failures.len(), names.len()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pair_splits_on_the_first_equals_sign() {
+        assert_eq!(parse_pair("KEY=a=b").unwrap(), ("KEY", "a=b"));
+        assert_eq!(parse_pair("KEY=").unwrap(), ("KEY", ""));
+    }
+
+    #[test]
+    fn parse_pair_errors_never_show_the_value() {
+        for pair in ["=hunter2", "hunter2"] {
+            let err = parse_pair(pair).unwrap_err().to_string();
+            assert!(!err.contains("hunter2"), "{}", err);
+            assert!(err.ends_with("expected KEY=VALUE"), "{}", err);
+        }
+    }
 }
@@ -1,23 +1,30 @@
-use super::CommandBase;
-use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
-use dialoguer::{FuzzySelect, Input};
+use super::{confirm_by_typing, confirm_or_cancel, docker_not_found, prompt_error, run_bulk, Cancelled, CommandBase, CommandError, ConfirmArgs, OutputFormat};
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use dialoguer::console::{style, Term};
+use dialoguer::{FuzzySelect, Input, MultiSelect, Select};
 use difference::{Changeset, Difference};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::env;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 use std::process::Command;
-use tabled::Table;
-use tungstenite::connect;
+use std::thread;
+use std::time::Duration;
+use tabled::{Table, Tabled};
 use tungstenite::http::Uri;
 use tungstenite::ClientRequestBuilder;
 
-use crate::api::types::{DisplayHashMap, DisplayOption, Service};
-use crate::api::APIClient;
+use crate::api::types::{DisplayHashMap, DisplayOption, RestartPolicy, Service};
+use crate::api::websocket::connect_websocket;
+use crate::cron::check_schedule;
+use crate::image::{check_image, ImageRef};
+use crate::util::{format_duration, parse_duration};
+use crate::api::{ApiError, MolnettApi};
 
 #[derive(Debug, Parser)]
 #[command(
@@ -61,119 +68,717 @@ pub enum Commands {
     Delete(Delete),
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Default, Parser)]
 pub struct Deploy {
-    #[arg(help = "Path to molnett manifest", default_value("./molnett.yaml"))]
+    #[arg(help = "Path to molnett manifest, - reads it from stdin", default_value("./molnett.yaml"))]
     manifest: String,
-    #[arg(long, help = "Skip confirmation", default_missing_value("true"), default_value("false"), num_args(0..=1), require_equals(true))]
-    no_confirm: Option<bool>,
+    #[arg(short, long, help = "Manifest to deploy, can be repeated to merge overrides onto a base. Replaces MANIFEST", value_name = "FILE")]
+    file: Vec<String>,
+    #[arg(long, value_enum, help = "Format of the manifest. Default is JSON for .json files and YAML otherwise, including stdin")]
+    format: Option<ManifestFormat>,
+    #[arg(long, help = "Environment to deploy to, applying its section under `environments` in the manifest. Default is the manifest's environment, then the default environment in the config")]
+    env: Option<String>,
+    #[command(flatten)]
+    confirm: ConfirmArgs,
+    #[arg(long, help = "Show the diff and ask before deploying each service, even with --yes. Declined services are skipped instead of cancelling the deploy")]
+    confirm_each: bool,
+    #[arg(long, help = "Delete secrets in the environment that no service references")]
+    prune_secrets: bool,
+    #[arg(long, help = "Compare against the service in another environment first: fail if secrets it has are missing here, and warn about differing env vars", value_name = "SOURCE_ENV")]
+    env_from: Option<String>,
+    #[arg(long, help = "Only deploy the named service, can be repeated", value_name = "NAME")]
+    only: Vec<String>,
+    #[arg(long, help = "Don't deploy the named service, can be repeated", value_name = "NAME")]
+    exclude: Vec<String>,
+    #[arg(long, help = "Number of unchanged lines to show around changes in the diff. Default is to show everything", value_name = "N")]
+    diff_context: Option<usize>,
+    #[arg(long, value_enum, default_value_t, help = "Format of the diff shown before deploying")]
+    diff_format: DiffFormat,
+    #[arg(long, help = "Print the diff even when not asking for confirmation, e.g. with --yes in CI")]
+    show_diff: bool,
+    #[arg(long, help = "Don't wrap long diff lines at the terminal width")]
+    no_wrap: bool,
+    #[arg(long, help = "Override a manifest field, e.g. service.image=foo:bar, can be repeated", value_name = "PATH=VALUE")]
+    set: Vec<String>,
+    #[arg(long, help = "Replace the image of the named service, can be repeated", value_name = "SERVICE=IMAGE")]
+    image: Vec<String>,
+    #[arg(long, help = "Exit with an error if no service changed", conflicts_with = "fail_on_change")]
+    fail_on_no_change: bool,
+    #[arg(long, help = "Exit with an error if any service changed")]
+    fail_on_change: bool,
+    #[arg(short, long, value_enum, default_value_t, help = "Output format of the deploy results")]
+    output: OutputFormat,
+    #[arg(long, help = "Only report services that changed, summarizing the unchanged ones")]
+    quiet_unchanged: bool,
+    #[arg(long, value_enum, default_value_t, help = "Wrap each service's output in collapsible CI log sections. auto detects GitHub Actions and GitLab CI")]
+    progress: Progress,
+    #[arg(long, help = "How long to wait for the API to accept the deploy, e.g. 30s or 2m", value_parser = parse_duration, value_name = "DURATION")]
+    timeout: Option<Duration>,
+}
+
+/// What happened to a service during a deploy.
+#[derive(Debug, PartialEq, Eq)]
+enum DeployOutcome {
+    Unchanged,
+    Deployed,
+    Declined,
+}
+
+#[derive(Serialize, Tabled)]
+struct DeployResult<'a> {
+    service: &'a str,
+    environment: &'a str,
+    image: &'a str,
+    status: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    Yaml,
+    Json,
+}
+
+/// Which CI system's log markers to print around each service's output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Progress {
+    #[default]
+    Auto,
+    Github,
+    Gitlab,
+    Plain,
+}
+
+impl Progress {
+    fn resolve(self) -> Self {
+        match self {
+            Progress::Auto if env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true") => Progress::Github,
+            Progress::Auto if env::var_os("GITLAB_CI").is_some() => Progress::Gitlab,
+            Progress::Auto => Progress::Plain,
+            progress => progress,
+        }
+    }
+
+    fn start(self, name: &str, title: &str) {
+        match self {
+            Progress::Github => println!("::group::{}", title),
+            Progress::Gitlab => println!(
+                "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}",
+                chrono::Utc::now().timestamp(),
+                gitlab_section(name),
+                title
+            ),
+            _ => {}
+        }
+    }
+
+    fn end(self, name: &str) {
+        match self {
+            Progress::Github => println!("::endgroup::"),
+            Progress::Gitlab => println!(
+                "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+                chrono::Utc::now().timestamp(),
+                gitlab_section(name)
+            ),
+            _ => {}
+        }
+    }
+
+    fn notice(self, message: &str) {
+        if self == Progress::Github {
+            println!("::notice::{}", message);
+        }
+    }
+
+    fn error(self, message: &str) {
+        if self == Progress::Github {
+            println!("::error::{}", message.replace('\n', "%0A"));
+        }
+    }
+}
+
+/// GitLab section names may only contain letters, digits, '_', '.' and '-'.
+fn gitlab_section(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "_.-".contains(c) { c } else { '_' })
+        .collect::<String>();
+    format!("deploy_{}", name)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    #[default]
+    Text,
+    Json,
 }
 
+/// The manifest format version written by this molnctl. Manifests without a
+/// `version` field predate versioning and are read as version 1.
+const MANIFEST_VERSION: u32 = 1;
+
 #[derive(Deserialize, Debug, Serialize)]
 pub struct Manifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    /// Empty when the manifest leaves the environment to --env or the
+    /// default environment in the config.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     environment: String,
     service: Service,
+    /// Per environment overrides of `service`, see `apply_environment_overrides`.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    environments: IndexMap<String, serde_yaml::Value>,
 }
 
 impl Deploy {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
-        let manifest = read_manifest(&self.manifest)?;
+        let (path, mut value) = match self.file.split_first() {
+            None => (self.manifest.clone(), read_manifest_value(&self.manifest, self.format)?),
+            Some((first, rest)) => {
+                let mut value = read_manifest_value(first, self.format)?;
+                for path in rest {
+                    merge_manifest_values(&mut value, read_manifest_value(path, self.format)?);
+                }
+                (self.file.join(" + "), value)
+            }
+        };
+        let env = self
+            .env
+            .as_deref()
+            .or_else(|| value.get("environment").and_then(|env| env.as_str()))
+            .filter(|env| !env.is_empty());
+        let env = match env {
+            Some(env) => env.to_string(),
+            None => default_environment(base)?,
+        };
+        apply_environment_overrides(&mut value, &env)?;
+        for pair in &self.set {
+            set_manifest_field(&mut value, pair)?;
+        }
+        let mut manifest = parse_manifest(&path, value)?;
+        self.override_images(&mut manifest)?;
+        check_image(&manifest.service.image)?;
 
         let env_exists = base
             .api_client()
             .get_environments(token, &org_name)?
             .contains(&manifest.environment);
         if !env_exists {
-            return Err(anyhow!(
+            return Err(ApiError::NotFound(format!(
                 "Environment {} does not exist",
                 manifest.environment
-            ));
+            ))
+            .into());
         }
 
-        if let Some(false) = self.no_confirm {
-            let response = base.api_client().get_service(
-                token,
-                &org_name,
-                &manifest.environment,
-                &manifest.service.name,
-            );
+        if let Some(source) = &self.env_from {
+            self.compare_with_source(base, token, &org_name, &manifest, source)?;
+        }
 
-            let existing_svc_yaml = match response? {
-                Some(svc) => {
-                    if svc == manifest.service {
-                        println!("no changes detected");
-                        return Ok(());
-                    }
-                    serde_yaml::to_string(&svc)?
+        let mut total = 0;
+        let mut changed = 0;
+        let status = if !self.is_selected(&manifest)? {
+            "skipped"
+        } else {
+            total += 1;
+            let progress = self.progress.resolve();
+            progress.start(&manifest.service.name, &format!("Deploy {}", manifest.service.name));
+            let outcome = self.deploy_service(base, token, &org_name, &manifest);
+            progress.end(&manifest.service.name);
+            match &outcome {
+                Ok(DeployOutcome::Deployed) => progress.notice(&format!(
+                    "Deployed {} to {}",
+                    manifest.service.name, manifest.environment
+                )),
+                Err(err) => progress.error(&format!("Deploying {} failed: {}", manifest.service.name, err)),
+                Ok(_) => {}
+            }
+            match outcome? {
+                DeployOutcome::Declined if self.confirm_each => "declined",
+                DeployOutcome::Declined => return Err(Cancelled.into()),
+                DeployOutcome::Deployed => {
+                    changed += 1;
+                    "deployed"
+                }
+                DeployOutcome::Unchanged => "unchanged",
+            }
+        };
+
+        self.check_secrets(base, token, &org_name, &manifest)?;
+
+        let mut results = vec![DeployResult {
+            service: &manifest.service.name,
+            environment: &manifest.environment,
+            image: &manifest.service.image,
+            status,
+        }];
+        let unchanged = results.iter().filter(|r| r.status == "unchanged").count();
+        if self.quiet_unchanged {
+            results.retain(|r| r.status != "unchanged");
+        }
+        match self.output {
+            OutputFormat::Table => {
+                if !results.is_empty() {
+                    println!("{}", Table::new(&results));
+                }
+                println!("{} of {} services changed", changed, total);
+                if self.quiet_unchanged && unchanged > 0 {
+                    println!("{} unchanged", unchanged);
+                }
+            }
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results)?),
+        }
+        if self.fail_on_no_change && changed == 0 {
+            return Err(anyhow!("No services changed"));
+        }
+        if self.fail_on_change && changed > 0 {
+            return Err(anyhow!("{} services changed", changed));
+        }
+        Ok(())
+    }
+
+    /// Applies --image overrides to the manifest's service.
+    fn override_images(&self, manifest: &mut Manifest) -> Result<()> {
+        for pair in &self.image {
+            let (name, image) = match pair.split_once('=') {
+                Some((name, image)) if !name.is_empty() && !image.is_empty() => (name, image),
+                _ => {
+                    return Err(CommandError::Usage(format!(
+                        "Invalid --image '{}', expected SERVICE=IMAGE",
+                        pair
+                    ))
+                    .into())
                 }
-                None => "".to_string(),
             };
-            let new_svc_yaml = serde_yaml::to_string(&manifest.service)?;
-            self.render_diff(existing_svc_yaml, new_svc_yaml)?;
-            let selection = self.user_confirmation();
-            if selection == 0 {
-                println!("Cancelling...");
-                return Ok(());
+            if name != manifest.service.name {
+                return Err(CommandError::Usage(format!(
+                    "Service {} is not in the manifest",
+                    name
+                ))
+                .into());
             }
+            manifest.service.image = image.to_string();
         }
+        Ok(())
+    }
 
-        let result = base.api_client().deploy_service(
+    /// Applies --only and --exclude to the manifest's service.
+    fn is_selected(&self, manifest: &Manifest) -> Result<bool> {
+        let name = &manifest.service.name;
+        if let Some(unknown) = self.only.iter().find(|only| *only != name) {
+            return Err(CommandError::Usage(format!(
+                "Service {} is not in the manifest",
+                unknown
+            ))
+            .into());
+        }
+        Ok(!self.exclude.contains(name))
+    }
+
+    /// Deploys the manifest's service unless it is already up to date.
+    fn deploy_service(
+        &self,
+        base: &CommandBase,
+        token: &str,
+        org_name: &str,
+        manifest: &Manifest,
+    ) -> Result<DeployOutcome> {
+        let existing_svc = base.api_client().get_service(
             token,
-            &org_name,
+            org_name,
             &manifest.environment,
-            manifest.service,
+            &manifest.service.name,
         )?;
-        println!("Service {} deployed", result.name);
+        // Compared by content hash rather than field by field, so a remote
+        // service that only lists its fields or variables in another order,
+        // or leaves out fields the manifest sets to their defaults, isn't
+        // redeployed.
+        if let Some(existing) = &existing_svc {
+            if existing.content_hash()? == manifest.service.content_hash()? {
+                return Ok(DeployOutcome::Unchanged);
+            }
+        }
+
+        let ask = self.confirm_each || self.confirm.needs_confirmation();
+        if ask || self.show_diff {
+            match self.diff_format {
+                DiffFormat::Text => {
+                    let existing_svc_yaml = match &existing_svc {
+                        Some(svc) => serde_yaml::to_string(svc)?,
+                        None => "".to_string(),
+                    };
+                    let new_svc_yaml = serde_yaml::to_string(&manifest.service)?;
+                    self.render_diff(existing_svc_yaml, new_svc_yaml)?;
+                }
+                DiffFormat::Json => {
+                    let diff = service_json_diff(existing_svc.as_ref(), &manifest.service)?;
+                    println!("{}", serde_json::to_string_pretty(&diff)?);
+                }
+            }
+        }
+        if ask && self.user_confirmation()? == 0 {
+            return Ok(DeployOutcome::Declined);
+        }
+
+        base.api_client()
+            .deploy_service(
+                token,
+                org_name,
+                &manifest.environment,
+                manifest.service.clone(),
+                self.timeout,
+            )
+            .map_err(|err| match self.timeout {
+                Some(timeout) if is_timeout(&err) => err.context(format!(
+                    "Deploy request timed out after {}. It may still have been accepted, check with `molnctl services list --env {}`",
+                    format_duration(timeout), manifest.environment
+                )),
+                _ => err,
+            })?;
+        Ok(DeployOutcome::Deployed)
+    }
+
+    /// Checks the manifest against the same service in `source` before
+    /// promoting it. Secret values can't be read through the API, so missing
+    /// secrets are reported for the user to set instead of being copied.
+    fn compare_with_source(
+        &self,
+        base: &CommandBase,
+        token: &str,
+        org_name: &str,
+        manifest: &Manifest,
+        source: &str,
+    ) -> Result<()> {
+        let api = base.api_client();
+        let secret_names = |env: &str| -> Result<Vec<String>> {
+            Ok(api
+                .get_secrets(token, org_name, env)?
+                .secrets
+                .into_iter()
+                .map(|secret| secret.name)
+                .collect())
+        };
+        let source_secrets = secret_names(source)?;
+        let target_secrets = secret_names(&manifest.environment)?;
+
+        let source_svc = api.get_service(token, org_name, source, &manifest.service.name)?;
+        let env_of = |svc: &Service| svc.env.0.as_ref().map(|env| env.0.clone()).unwrap_or_default();
+        if let Some(source_svc) = &source_svc {
+            let source_env = env_of(source_svc);
+            let target_env = env_of(&manifest.service);
+            for (key, value) in &source_env {
+                match target_env.get(key) {
+                    None => println!("env {} is set in {} but not in the manifest", key, source),
+                    Some(target) if target != value => {
+                        println!("env {} differs from {}: {:?} -> {:?}", key, source, value, target)
+                    }
+                    Some(_) => {}
+                }
+            }
+            for key in target_env.keys().filter(|key| !source_env.contains_key(*key)) {
+                println!("env {} is set in the manifest but not in {}", key, source);
+            }
+        } else {
+            println!("Service {} does not exist in {}, comparing secrets only", manifest.service.name, source);
+        }
+
+        let referenced = match &manifest.service.secrets {
+            DisplayOption(Some(DisplayHashMap(secrets))) => secrets.values().cloned().collect::<Vec<_>>(),
+            DisplayOption(None) => Vec::new(),
+        };
+        let missing = referenced
+            .iter()
+            .filter(|name| source_secrets.contains(name) && !target_secrets.contains(name))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        println!(
+            "The following secrets exist in {} but not in {}:",
+            source, manifest.environment
+        );
+        for name in &missing {
+            println!("  {}", name);
+        }
+        Err(anyhow!(
+            "{} secrets are missing in {}. Secret values can't be read back from {}, set them with `molnctl secrets set --env {} NAME=VALUE`",
+            missing.len(),
+            manifest.environment,
+            source,
+            manifest.environment
+        ))
+    }
+
+    /// Reports secrets in the manifest's environment that no service there
+    /// references, and deletes them with --prune-secrets.
+    fn check_secrets(
+        &self,
+        base: &CommandBase,
+        token: &str,
+        org_name: &str,
+        manifest: &Manifest,
+    ) -> Result<()> {
+        // The manifest holds a single service, the other services deployed
+        // to the environment may use secrets too.
+        let services = base
+            .api_client()
+            .get_services(token, org_name, &manifest.environment)?
+            .services;
+        let referenced = services
+            .iter()
+            .chain(std::iter::once(&manifest.service))
+            .filter_map(|svc| svc.secrets.0.as_ref())
+            .flat_map(|secrets| secrets.0.values())
+            .collect::<HashSet<_>>();
+        let unreferenced = base
+            .api_client()
+            .get_secrets(token, org_name, &manifest.environment)?
+            .secrets
+            .into_iter()
+            .map(|secret| secret.name)
+            .filter(|name| !referenced.contains(name))
+            .collect::<Vec<_>>();
+        if unreferenced.is_empty() {
+            return Ok(());
+        }
+
+        // This is a side report, stdout is kept for the deploy results.
+        eprintln!(
+            "The following secrets in environment {} are not referenced by any service:",
+            manifest.environment
+        );
+        for name in &unreferenced {
+            eprintln!("  {}", name);
+        }
+        if !self.prune_secrets {
+            return Ok(());
+        }
+
+        if self.confirm.needs_confirmation() {
+            eprintln!("They will be deleted.");
+            if !confirm_by_typing(&manifest.environment)? {
+                eprintln!("Skipping secret prune");
+                return Ok(());
+            }
+        }
+
+        let mut failed = 0;
+        for name in &unreferenced {
+            match base.api_client().delete_secret(token, org_name, &manifest.environment, name) {
+                Ok(()) => eprintln!("Secret {} pruned", name),
+                Err(err) => {
+                    eprintln!("Failed to prune secret {}: {}", name, err);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            return Err(anyhow!("Failed to prune {} of {} secrets", failed, unreferenced.len()));
+        }
         Ok(())
     }
 
-    fn user_confirmation(&self) -> usize {
+    fn user_confirmation(&self) -> Result<usize> {
         FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
             .with_prompt("Do you want to apply the above changes?")
             .items(&["no", "yes"])
             .default(0)
             .interact()
-            .unwrap()
+            .map_err(prompt_error)
     }
 
     fn render_diff(&self, a: String, b: String) -> Result<()> {
-        let Changeset { diffs, .. } = Changeset::new(&a, &b, "\n");
-        let mut t = match term::stdout() {
-            Some(stdout) => stdout,
-            None => {
-                return Err(anyhow!(
-                    "Could not render diff. Consider using --no-confirm"
-                ))
-            }
-        };
-        for i in 0..diffs.len() {
-            match diffs[i] {
-                Difference::Same(ref x) => {
-                    t.reset().unwrap();
-                    writeln!(t, " {}", x)?;
-                }
-                Difference::Add(ref x) => {
-                    t.fg(term::color::GREEN).unwrap();
-                    writeln!(t, "+{}", x)?;
+        let mut lines = diff_lines(&a, &b);
+        if let Some(context) = self.diff_context {
+            lines = collapse_unchanged(lines, context);
+        }
+
+        let width = if self.no_wrap { None } else { diff_width() };
+        for line in lines {
+            let (marker, text) = match &line {
+                DiffLine::Same(x) => (' ', x.as_str()),
+                DiffLine::Add(x) => ('+', x.as_str()),
+                DiffLine::Rem(x) => ('-', x.as_str()),
+                DiffLine::Skipped(count) => {
+                    println!(" ... ({} lines) ...", count);
+                    continue;
                 }
-                Difference::Rem(ref x) => {
-                    t.fg(term::color::RED).unwrap();
-                    writeln!(t, "-{}", x)?;
+            };
+            let chunks = match width {
+                Some(width) => wrap_line(text, width),
+                None => vec![text],
+            };
+            // Continuation lines repeat the marker so they still read as
+            // part of the same change.
+            for chunk in chunks {
+                let out = format!("{}{}", marker, chunk);
+                match marker {
+                    '+' => println!("{}", style(out).green()),
+                    '-' => println!("{}", style(out).red()),
+                    _ => println!("{}", out),
                 }
             }
         }
-        t.reset().unwrap();
-        t.flush().unwrap();
         Ok(())
     }
 }
 
+/// Structured diff between the deployed service and the manifest, with the
+/// added, removed and changed fields keyed by their dotted path.
+fn service_json_diff(existing: Option<&Service>, new: &Service) -> Result<serde_json::Value> {
+    let old = match existing {
+        Some(svc) => serde_json::to_value(svc)?,
+        None => serde_json::Value::Object(Default::default()),
+    };
+    let new_value = serde_json::to_value(new)?;
+
+    let mut diff = JsonDiff::default();
+    diff.compare("", &old, &new_value);
+    Ok(serde_json::json!({
+        "service": new.name,
+        "added": diff.added,
+        "removed": diff.removed,
+        "changed": diff.changed,
+    }))
+}
+
+#[derive(Default)]
+struct JsonDiff {
+    added: serde_json::Map<String, serde_json::Value>,
+    removed: serde_json::Map<String, serde_json::Value>,
+    changed: serde_json::Map<String, serde_json::Value>,
+}
+
+impl JsonDiff {
+    fn compare(&mut self, path: &str, old: &serde_json::Value, new: &serde_json::Value) {
+        use serde_json::Value;
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                for (key, old_value) in old {
+                    let key_path = join_path(path, key);
+                    match new.get(key) {
+                        Some(new_value) => self.compare(&key_path, old_value, new_value),
+                        None => {
+                            self.removed.insert(key_path, old_value.clone());
+                        }
+                    }
+                }
+                for (key, new_value) in new {
+                    if !old.contains_key(key) {
+                        self.added.insert(join_path(path, key), new_value.clone());
+                    }
+                }
+            }
+            _ if old != new => {
+                self.changed.insert(
+                    path.to_string(),
+                    serde_json::json!({ "from": old, "to": new }),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum DiffLine {
+    Same(String),
+    Add(String),
+    Rem(String),
+    Skipped(usize),
+}
+
+/// Line based diff of `a` and `b`, one entry per line.
+fn diff_lines(a: &str, b: &str) -> Vec<DiffLine> {
+    let Changeset { diffs, .. } = Changeset::new(a, b, "\n");
+    let mut lines = Vec::new();
+    for diff in diffs {
+        match diff {
+            Difference::Same(x) => lines.extend(x.split('\n').map(|l| DiffLine::Same(l.into()))),
+            Difference::Add(x) => lines.extend(x.split('\n').map(|l| DiffLine::Add(l.into()))),
+            Difference::Rem(x) => lines.extend(x.split('\n').map(|l| DiffLine::Rem(l.into()))),
+        }
+    }
+    lines
+}
+
+/// Columns available for a diff line after its marker, or `None` when stdout
+/// is not a terminal and lines should be left alone.
+fn diff_width() -> Option<usize> {
+    let term = Term::stdout();
+    if !term.is_term() {
+        return None;
+    }
+    let (_, cols) = term.size_checked()?;
+    (cols as usize).checked_sub(1).filter(|width| *width > 0)
+}
+
+/// Splits `line` into chunks of at most `width` characters, breaking after a
+/// space where there is one.
+fn wrap_line(line: &str, width: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = line;
+    while let Some((end, _)) = rest.char_indices().nth(width) {
+        let split = match rest[..end].rfind(' ') {
+            Some(space) if space > 0 => space + 1,
+            _ => end,
+        };
+        chunks.push(&rest[..split]);
+        rest = &rest[split..];
+    }
+    chunks.push(rest);
+    chunks
+}
+
+/// Keeps at most `context` unchanged lines before and after each change and
+/// replaces the rest of each unchanged run with a `Skipped` marker.
+fn collapse_unchanged(lines: Vec<DiffLine>, context: usize) -> Vec<DiffLine> {
+    let mut result = Vec::new();
+    let mut run = Vec::new();
+    let mut seen_change = false;
+
+    let flush = |run: &mut Vec<DiffLine>,
+                 result: &mut Vec<DiffLine>,
+                 before_change: bool,
+                 after_change: bool| {
+        let keep_head = if after_change { context } else { 0 };
+        let keep_tail = if before_change { context } else { 0 };
+        if run.len() <= keep_head + keep_tail {
+            result.append(run);
+            return;
+        }
+        let tail = run.split_off(run.len() - keep_tail);
+        let skipped = run.len() - keep_head;
+        run.truncate(keep_head);
+        result.append(run);
+        result.push(DiffLine::Skipped(skipped));
+        result.extend(tail);
+    };
+
+    for line in lines {
+        if let DiffLine::Same(_) = line {
+            run.push(line);
+            continue;
+        }
+        flush(&mut run, &mut result, true, seen_change);
+        seen_change = true;
+        result.push(line);
+    }
+    flush(&mut run, &mut result, false, seen_change);
+    result
+}
+
 #[derive(Parser, Debug)]
 #[command(
     author,
@@ -191,6 +796,37 @@ pub struct Initialize {
         default_value("./molnett.yaml")
     )]
     manifest: String,
+    #[arg(long, help = "Load the service's environment variables from a .env file", value_name = "PATH")]
+    env_from_file: Option<String>,
+    #[arg(long, value_enum, help = "Start from a template with the port, restart policy and environment filled in")]
+    template: Option<Template>,
+}
+
+/// Starting points for `services init`, each one a partial service in
+/// `templates/`.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Template {
+    Web,
+    Worker,
+    Cron,
+}
+
+impl Template {
+    fn source(self) -> &'static str {
+        match self {
+            Template::Web => include_str!("templates/web.yaml"),
+            Template::Worker => include_str!("templates/worker.yaml"),
+            Template::Cron => include_str!("templates/cron.yaml"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ServiceTemplate {
+    container_port: u16,
+    restart_policy: RestartPolicy,
+    #[serde(default)]
+    env: IndexMap<String, String>,
 }
 
 impl Initialize {
@@ -201,28 +837,23 @@ impl Initialize {
                 "The file {} exists, do you want to overwrite it?",
                 self.manifest
             );
-            let selection = FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                .with_prompt(prompt)
-                .items(&["no", "yes"])
-                .default(0)
-                .interact()
-                .unwrap();
-            if selection == 0 {
-                println!("Cancelling...");
-                return Ok(());
-            }
+            confirm_or_cancel(&prompt)?;
         }
 
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
-        let manifest = ManifestBuilder::new(token.to_string(), base.api_client(), base.get_org()?)
+        let builder = ManifestBuilder::new(token.to_string(), base.api_client(), base.get_org()?)
             .get_env_name()?
-            .get_service_name()?
-            .get_port()?
-            .get_image()?
+            .get_service_name()?;
+        let builder = match self.template {
+            Some(Template::Cron) => builder.apply_template(Template::Cron)?.get_schedule()?,
+            Some(template) => builder.apply_template(template)?,
+            None => builder.get_port()?.get_restart_policy()?,
+        };
+        let manifest = builder
+            .get_command()?
+            .get_image(&base.get_org_id()?)?
+            .get_env_from_file(self.env_from_file.as_deref())?
             .build();
 
         write_manifest(&self.manifest, &manifest)?;
@@ -232,20 +863,21 @@ impl Initialize {
     }
 }
 
-struct ManifestBuilder {
+struct ManifestBuilder<'a> {
     token: String,
-    api_client: APIClient,
+    api_client: &'a dyn MolnettApi,
     org_name: String,
     manifest: Manifest,
 }
 
-impl ManifestBuilder {
-    pub fn new(token: String, api_client: APIClient, org_name: String) -> ManifestBuilder {
+impl<'a> ManifestBuilder<'a> {
+    pub fn new(token: String, api_client: &'a dyn MolnettApi, org_name: String) -> ManifestBuilder<'a> {
         ManifestBuilder {
             token,
             api_client,
             org_name,
             manifest: Manifest {
+                version: Some(MANIFEST_VERSION),
                 environment: "".to_string(),
                 service: Service {
                     name: "".to_string(),
@@ -253,7 +885,12 @@ impl ManifestBuilder {
                     container_port: 0,
                     env: DisplayOption(Some(DisplayHashMap(IndexMap::new()))),
                     secrets: DisplayOption(Some(DisplayHashMap(IndexMap::new()))),
+                    restart_policy: DisplayOption(None),
+                    schedule: DisplayOption(None),
+                    command: Vec::new(),
+                    args: Vec::new(),
                 },
+                environments: IndexMap::new(),
             },
         }
     }
@@ -261,7 +898,8 @@ impl ManifestBuilder {
     pub fn get_service_name(mut self) -> Result<Self> {
         self.manifest.service.name = Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
             .with_prompt("Please enter a name for your service: ")
-            .interact_text()?;
+            .interact_text()
+            .map_err(prompt_error)?;
 
         Ok(self)
     }
@@ -274,7 +912,8 @@ impl ManifestBuilder {
         let selection = FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
             .with_prompt("Please select the environment to deploy the service in: ")
             .items(&envs[..])
-            .interact()?;
+            .interact()
+            .map_err(prompt_error)?;
         self.manifest.environment = envs[selection].to_string();
 
         Ok(self)
@@ -284,14 +923,101 @@ impl ManifestBuilder {
         self.manifest.service.container_port =
             Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
                 .with_prompt("Please enter the port your container is listening on: ")
-                .interact_text()?;
+                .interact_text()
+                .map_err(prompt_error)?;
 
         Ok(self)
     }
 
-    pub fn get_image(mut self) -> Result<Self> {
-        self.manifest.service.image =
-            get_image_name(&self.api_client, &self.token, &self.org_name, &None, &None)?;
+    pub fn get_restart_policy(mut self) -> Result<Self> {
+        let selection = Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
+            .with_prompt("When should your container be restarted after it exits?")
+            .items(&RestartPolicy::ALL)
+            .default(0)
+            .interact()
+            .map_err(prompt_error)?;
+        self.manifest.service.restart_policy = DisplayOption(Some(RestartPolicy::ALL[selection]));
+
+        Ok(self)
+    }
+
+    pub fn get_schedule(mut self) -> Result<Self> {
+        let schedule: String = Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
+            .with_prompt("Please enter the cron schedule to run the job on, e.g. 0 3 * * *: ")
+            .validate_with(|input: &String| check_schedule(input).map_err(|err| err.to_string()))
+            .interact_text()
+            .map_err(prompt_error)?;
+        self.manifest.service.schedule = DisplayOption(Some(schedule.trim().to_string()));
+
+        Ok(self)
+    }
+
+    pub fn get_command(mut self) -> Result<Self> {
+        let prompt = |prompt: &str| -> Result<Vec<String>> {
+            let input: String = Input::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                .with_prompt(prompt)
+                .allow_empty(true)
+                .interact_text()
+                .map_err(prompt_error)?;
+            Ok(input.split_whitespace().map(str::to_string).collect())
+        };
+        self.manifest.service.command =
+            prompt("Command to run instead of the image's entrypoint, leave empty to keep it: ")?;
+        self.manifest.service.args =
+            prompt("Arguments to pass instead of the image's CMD, leave empty to keep them: ")?;
+
+        Ok(self)
+    }
+
+    pub fn apply_template(mut self, template: Template) -> Result<Self> {
+        let template: ServiceTemplate = serde_yaml::from_str(template.source())?;
+        self.manifest.service.container_port = template.container_port;
+        self.manifest.service.restart_policy = DisplayOption(Some(template.restart_policy));
+        self.manifest.service.env = DisplayOption(Some(DisplayHashMap(template.env)));
+        Ok(self)
+    }
+
+    pub fn get_image(mut self, org_id: &str) -> Result<Self> {
+        self.manifest.service.image = get_image_name(org_id, &None, &None)?;
+        Ok(self)
+    }
+
+    pub fn get_env_from_file(mut self, path: Option<&str>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(self);
+        };
+        let vars = read_env_file(path)?;
+        if vars.is_empty() {
+            return Ok(self);
+        }
+
+        let keys = vars.keys().collect::<Vec<_>>();
+        let secret_keys = MultiSelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
+            .with_prompt("Select the variables that should be read from secrets instead: ")
+            .items(&keys[..])
+            .interact()
+            .map_err(prompt_error)?;
+
+        // Add to what a template may have set, the file wins on conflicts.
+        let mut env = self.manifest.service.env.0.take().map(|env| env.0).unwrap_or_default();
+        let mut secrets = self.manifest.service.secrets.0.take().map(|secrets| secrets.0).unwrap_or_default();
+        for (i, (key, value)) in vars.iter().enumerate() {
+            if secret_keys.contains(&i) {
+                env.shift_remove(key);
+                secrets.insert(key.clone(), key.clone());
+            } else {
+                env.insert(key.clone(), value.clone());
+            }
+        }
+        if !secrets.is_empty() {
+            println!(
+                "Remember to create the secrets {} with `molnctl secrets set`",
+                secrets.keys().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        self.manifest.service.env = DisplayOption(Some(DisplayHashMap(env)));
+        self.manifest.service.secrets = DisplayOption(Some(DisplayHashMap(secrets)));
         Ok(self)
     }
 
@@ -300,10 +1026,36 @@ impl ManifestBuilder {
     }
 }
 
+/// Reads a .env style file of KEY=VALUE lines. Blank lines, comments and an
+/// optional `export` prefix are ignored, and values may be quoted.
+fn read_env_file(path: &str) -> Result<IndexMap<String, String>> {
+    let mut content = String::new();
+    File::open(path)
+        .with_context(|| format!("Failed to open {}", path))?
+        .read_to_string(&mut content)?;
+
+    let mut vars = IndexMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+            _ => return Err(anyhow!("{}:{}: expected KEY=VALUE", path, i + 1)),
+        };
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+            .unwrap_or(value);
+        vars.insert(key.to_string(), value.to_string());
+    }
+    Ok(vars)
+}
+
 fn get_image_name(
-    api_client: &APIClient,
-    token: &str,
-    org_name: &str,
+    org_id: &str,
     tag: &Option<String>,
     name: &Option<String>,
 ) -> Result<String> {
@@ -318,7 +1070,6 @@ fn get_image_name(
         };
         image_name.to_string()
     };
-    let org_id = api_client.get_org(token, org_name)?.id;
 
     let image_tag = if tag.is_some() {
         tag.as_ref().unwrap().to_string()
@@ -331,10 +1082,12 @@ fn get_image_name(
         String::from_utf8_lossy(&git_output.stdout).to_string()
     };
 
-    return Ok(format!(
+    let image = format!(
         "register.molnett.org/{}/{}:{}",
         org_id, image_name, image_tag.trim()
-    ));
+    );
+    ImageRef::parse(&image)?;
+    Ok(image)
 }
 
 #[derive(Parser, Debug)]
@@ -345,16 +1098,19 @@ pub struct ImageName {
     update_manifest: Option<String>,
     #[arg(short, long, help = "Override image name. Default is directory name")]
     image_name: Option<String>,
+    #[arg(long, help = "Only print the org id used in the image path", conflicts_with = "update_manifest")]
+    id_only: bool,
 }
 
 impl ImageName {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let org_id = base.get_org_id()?;
+        if self.id_only {
+            println!("{}", org_id);
+            return Ok(());
+        }
 
-        let image_name = get_image_name(&base.api_client(), token, &base.get_org()?, &self.tag, &self.image_name)?;
+        let image_name = get_image_name(&org_id, &self.tag, &self.image_name)?;
         if let Some(path) = self.update_manifest.clone() {
             let mut manifest = read_manifest(&path)?;
             manifest.service.image = image_name.clone();
@@ -370,19 +1126,37 @@ impl ImageName {
 pub struct List {
     #[arg(long, help = "Environment to list the services of")]
     env: String,
+    #[arg(long, help = "Keep refreshing the list until interrupted")]
+    watch: bool,
+    #[arg(long, help = "Time between refreshes with --watch, e.g. 5s or 1m", value_parser = parse_duration, default_value = "2s", requires = "watch")]
+    interval: Duration,
 }
 
 impl List {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
+
+        if !self.watch {
+            return self.print_services(base, token, &org_name);
+        }
 
+        let term = Term::stdout();
+        loop {
+            term.clear_screen()?;
+            println!(
+                "Services in {}, refreshing every {}. Press Ctrl-C to exit.\n",
+                self.env, format_duration(self.interval)
+            );
+            self.print_services(base, token, &org_name)?;
+            thread::sleep(self.interval);
+        }
+    }
+
+    fn print_services(&self, base: &CommandBase, token: &str, org_name: &str) -> Result<()> {
         let response = base
             .api_client()
-            .get_services(token, &org_name, &self.env)?;
+            .get_services(token, org_name, &self.env)?;
 
         let table = Table::new(response.services).to_string();
         println!("{}", table);
@@ -393,40 +1167,311 @@ impl List {
 
 #[derive(Debug, Parser)]
 pub struct Delete {
-    #[arg(help = "Name of the service")]
-    name: String,
+    #[arg(help = "Name of the service", required_unless_present = "all", conflicts_with = "all")]
+    name: Option<String>,
+    #[arg(long, help = "Delete every service in the environment")]
+    all: bool,
+    #[arg(long, help = "Number of services to delete in parallel with --all", default_value_t = 4, requires = "all")]
+    concurrency: usize,
     #[arg(long, help = "Environment the service is in")]
     env: String,
-    #[arg(long, help = "Skip confirmation", default_missing_value("true"), default_value("false"), num_args(0..=1), require_equals(true))]
-    no_confirm: Option<bool>,
+    #[command(flatten)]
+    confirm: ConfirmArgs,
 }
 
 impl Delete {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        let name = match &self.name {
+            Some(name) => name,
+            None => return self.delete_all(base),
+        };
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
-
-        if let Some(false) = self.no_confirm {
-            let prompt = format!("Org: {}, Environment: {}, Service: {}. Are you sure you want to delete this service?", org_name, self.env, self.name);
-            FuzzySelect::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                .with_prompt(prompt)
-                .items(&["no", "yes"])
-                .default(0)
-                .interact()
-                .unwrap();
+        let token = base.get_token()?;
+
+        if self.confirm.needs_confirmation() {
+            let prompt = format!("Org: {}, Environment: {}, Service: {}. Are you sure you want to delete this service?", org_name, self.env, name);
+            confirm_or_cancel(&prompt)?;
         }
 
         base.api_client()
-            .delete_service(token, &org_name, &self.env, &self.name)?;
+            .delete_service(token, &org_name, &self.env, name)?;
+
+        println!("Service {} deleted", name);
+        Ok(())
+    }
+
+    fn delete_all(&self, base: &CommandBase) -> Result<()> {
+        let org_name = base.get_org()?;
+        let token = base.get_token()?;
+
+        let names = base
+            .api_client()
+            .get_services(token, &org_name, &self.env)?
+            .services
+            .into_iter()
+            .map(|svc| svc.name)
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            println!("No services in environment {}", self.env);
+            return Ok(());
+        }
+
+        if self.confirm.needs_confirmation() {
+            println!("The following services will be deleted from environment {}:", self.env);
+            for name in &names {
+                println!("  {}", name);
+            }
+            if !confirm_by_typing(&self.env)? {
+                return Err(Cancelled.into());
+            }
+        }
 
-        println!("Service {} deleted", self.name);
+        let failures = run_bulk(&names, self.concurrency, |name| {
+            base.api_client()
+                .delete_service(token, &org_name, &self.env, name)?;
+            println!("Service {} deleted", name);
+            Ok(())
+        });
+
+        if !failures.is_empty() {
+            for (name, err) in &failures {
+                eprintln!("Failed to delete service {}: {}", name, err);
+            }
+            return Err(anyhow!("Failed to delete {} of {} services", failures.len(), names.len()));
+        }
         Ok(())
     }
 }
 
+#[derive(Debug, Parser)]
+pub struct Up {
+    #[arg(help = "Path to molnett manifest", default_value("./molnett.yaml"))]
+    manifest: String,
+    #[arg(long, help = "Environment to deploy to instead of the manifest's, without changing the manifest")]
+    env: Option<String>,
+    #[arg(short, long, help = "Image tag to use, can be repeated to tag and push the image under each. The first one is deployed. Default is the current git commit")]
+    tag: Vec<String>,
+    #[arg(short, long, help = "Override image name. Default is directory name")]
+    image_name: Option<String>,
+    #[arg(long, help = "Skip building the image")]
+    no_build: bool,
+    #[arg(long, help = "Dockerfile to build. Default is Dockerfile or Containerfile in the current directory")]
+    dockerfile: Option<String>,
+    #[arg(long, help = "Label to set on the image, can be repeated", value_parser = parse_label, value_name = "KEY=VALUE")]
+    label: Vec<(String, String)>,
+    #[arg(long, help = "buildx builder to build with instead of the daemon's own, e.g. a standalone BuildKit added with `docker buildx create --driver remote tcp://buildkitd:1234`. The image is pushed from the builder", value_name = "NAME")]
+    builder: Option<String>,
+    #[arg(long, help = "Skip pushing the image")]
+    no_push: bool,
+    #[command(flatten)]
+    docker: DockerOptions,
+    #[command(flatten)]
+    confirm: ConfirmArgs,
+}
+
+fn parse_label(label: &str) -> Result<(String, String)> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(anyhow!("expected KEY=VALUE")),
+    }
+}
+
+impl Up {
+    /// Standard OCI labels for provenance, --label entries win over these.
+    fn labels(&self) -> IndexMap<String, String> {
+        let mut labels = IndexMap::new();
+        let revision = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+        if let Some(revision) = revision {
+            labels.insert("org.opencontainers.image.revision".to_string(), revision);
+        }
+        labels.insert(
+            "org.opencontainers.image.created".to_string(),
+            chrono::Utc::now().to_rfc3339(),
+        );
+        labels.insert(
+            "org.molnett.molnctl.version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        );
+        for (key, value) in &self.label {
+            labels.insert(key.clone(), value.clone());
+        }
+        labels
+    }
+
+    pub fn execute(&self, base: &CommandBase) -> Result<()> {
+        let org_id = base.get_org_id()?;
+        let images = if self.tag.is_empty() {
+            vec![get_image_name(&org_id, &None, &self.image_name)?]
+        } else {
+            self.tag
+                .iter()
+                .map(|tag| get_image_name(&org_id, &Some(tag.clone()), &self.image_name))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let image = images[0].clone();
+
+        if !self.no_build || !self.no_push {
+            check_docker_daemon(&self.docker)?;
+        }
+
+        if !self.no_build {
+            let dockerfile = find_dockerfile(&self.dockerfile).context("Build stage failed")?;
+            println!("==> Building {} from {}", images.join(", "), dockerfile);
+            let mut args = match &self.builder {
+                // A buildx builder may not share the daemon's image store, so
+                // the image is pushed from the builder itself.
+                Some(builder) => vec![
+                    "buildx".to_string(),
+                    "build".to_string(),
+                    "--builder".to_string(),
+                    builder.clone(),
+                    if self.no_push { "--load" } else { "--push" }.to_string(),
+                ],
+                None => vec!["build".to_string()],
+            };
+            args.push("-f".to_string());
+            args.push(dockerfile);
+            for image in &images {
+                args.push("-t".to_string());
+                args.push(image.clone());
+            }
+            for (key, value) in self.labels() {
+                args.push("--label".to_string());
+                args.push(format!("{}={}", key, value));
+            }
+            args.push(".".to_string());
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            run_docker(&self.docker, &args).context("Build stage failed")?;
+        }
+
+        let pushed_by_builder = self.builder.is_some() && !self.no_build;
+        if !self.no_push && !pushed_by_builder {
+            for image in &images {
+                println!("==> Pushing {}", image);
+                run_docker(&self.docker, &["push", image]).context("Push stage failed")?;
+            }
+        }
+
+        // Only the image is stamped into the manifest, --env applies to
+        // this deploy and leaves the manifest's environment alone.
+        println!("==> Updating image in {}", self.manifest);
+        let mut manifest = read_manifest(&self.manifest).context("Manifest stage failed")?;
+        manifest.service.image = image;
+        write_manifest(&self.manifest, &manifest).context("Manifest stage failed")?;
+
+        println!("==> Deploying {}", manifest.service.name);
+        let deploy = Deploy {
+            manifest: self.manifest.clone(),
+            env: self.env.clone(),
+            confirm: self.confirm.clone(),
+            ..Default::default()
+        };
+        deploy.execute(base).context("Deploy stage failed")
+    }
+}
+
+/// Names `docker build` is pointed at when no --dockerfile is given, in order.
+const DOCKERFILE_NAMES: &[&str] = &["Dockerfile", "Containerfile"];
+
+fn find_dockerfile(dockerfile: &Option<String>) -> Result<String> {
+    if let Some(dockerfile) = dockerfile {
+        if !Path::new(dockerfile).is_file() {
+            return Err(anyhow!("{} does not exist", dockerfile));
+        }
+        return Ok(dockerfile.clone());
+    }
+    DOCKERFILE_NAMES
+        .iter()
+        .find(|name| Path::new(name).is_file())
+        .map(|name| name.to_string())
+        .ok_or_else(|| {
+            anyhow!(
+                "No {} found in the current directory, use --dockerfile to point at one",
+                DOCKERFILE_NAMES.join(" or ")
+            )
+        })
+}
+
+/// Which Docker daemon `up` talks to. The docker CLI already reads
+/// DOCKER_HOST, DOCKER_CERT_PATH and DOCKER_TLS_VERIFY, these flags set them
+/// for the commands molnctl runs.
+#[derive(Clone, Debug, Default, Args)]
+pub struct DockerOptions {
+    #[arg(long, help = "Docker daemon to build and push with, e.g. tcp://docker:2376. Default is DOCKER_HOST or the local socket", value_name = "URL")]
+    docker_host: Option<String>,
+    #[arg(long, help = "Verify the Docker daemon's certificate and authenticate with a client certificate, like DOCKER_TLS_VERIFY=1")]
+    docker_tls: bool,
+    #[arg(long, help = "Directory with ca.pem, cert.pem and key.pem for --docker-tls. Default is DOCKER_CERT_PATH or ~/.docker", value_name = "DIR", requires = "docker_tls")]
+    docker_cert_path: Option<String>,
+}
+
+impl DockerOptions {
+    fn host(&self) -> Option<String> {
+        self.docker_host.clone().or_else(|| env::var("DOCKER_HOST").ok())
+    }
+
+    /// Makes sure the certificates are there before docker fails on them
+    /// with a less helpful message.
+    fn validate(&self) -> Result<()> {
+        let Some(dir) = &self.docker_cert_path else {
+            return Ok(());
+        };
+        for file in ["ca.pem", "cert.pem", "key.pem"] {
+            if !Path::new(dir).join(file).is_file() {
+                return Err(anyhow!("{} is missing from --docker-cert-path {}", file, dir));
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("docker");
+        if let Some(host) = &self.docker_host {
+            command.env("DOCKER_HOST", host);
+        }
+        if self.docker_tls {
+            command.env("DOCKER_TLS_VERIFY", "1");
+        }
+        if let Some(dir) = &self.docker_cert_path {
+            command.env("DOCKER_CERT_PATH", dir);
+        }
+        command
+    }
+}
+
+/// Fails with a hint instead of docker's own error when the daemon can't be
+/// reached, before any stage has started.
+fn check_docker_daemon(docker: &DockerOptions) -> Result<()> {
+    docker.validate()?;
+    let output = docker
+        .command()
+        .args(["version", "--format", "{{.Server.Version}}"])
+        .output()
+        .map_err(docker_not_found)?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let host = docker.host().unwrap_or_else(|| "the local socket".to_string());
+    Err(anyhow!(
+        "Could not connect to the Docker daemon at {}. Is Docker running? Set DOCKER_HOST or --docker-host if you use a remote daemon.\n{}",
+        host,
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
+
+fn run_docker(docker: &DockerOptions, args: &[&str]) -> Result<()> {
+    let status = docker.command().args(args).status().map_err(docker_not_found)?;
+    if !status.success() {
+        return Err(anyhow!("docker {} exited with {}", args[0], status));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Parser)]
 pub struct Logs {
     #[arg(help = "Path to molnett manifest", default_value("./molnett.yaml"))]
@@ -436,18 +1481,19 @@ pub struct Logs {
 impl Logs {
     pub fn execute(&self, base: &CommandBase) -> Result<()> {
         let org_name = base.get_org()?;
-        let token = base
-            .user_config()
-            .get_token()
-            .ok_or_else(|| anyhow!("No token found. Please login first."))?;
+        let token = base.get_token()?;
 
         let manifest = read_manifest(&self.manifest)?;
+        let env = match manifest.environment.as_str() {
+            "" => default_environment(base)?,
+            env => env.to_string(),
+        };
         let logurl: Uri = url::Url::parse(
             format!(
                 "{}/orgs/{}/envs/{}/svcs/{}/logs",
                 base.user_config().get_url().replace("http", "ws"),
                 org_name,
-                manifest.environment,
+                env,
                 manifest.service.name,
             )
             .as_str(),
@@ -457,10 +1503,11 @@ impl Logs {
         .parse()
         .unwrap();
 
-        let builder = ClientRequestBuilder::new(logurl)
+        let builder = ClientRequestBuilder::new(logurl.clone())
             .with_header("Authorization", format!("Bearer {}", token.to_owned()));
 
-        let (mut socket, _) = connect(builder).expect("Could not connect");
+        let mut socket = connect_websocket(&logurl, builder, base.tls(), base.proxy())
+            .context("Could not connect")?;
 
         loop {
             let msg = socket.read().expect("Error reading message");
@@ -469,16 +1516,647 @@ impl Logs {
     }
 }
 
+/// The environment to use when neither --env nor the manifest names one.
+fn default_environment(base: &CommandBase) -> Result<String> {
+    base.user_config()
+        .get_default_environment()
+        .map(str::to_string)
+        .ok_or_else(|| {
+            CommandError::Usage(
+                "No environment specified. Pass --env, set environment in the manifest or run `molnctl environments switch`"
+                    .to_string(),
+            )
+            .into()
+        })
+}
+
 fn read_manifest(path: &str) -> Result<Manifest> {
+    parse_manifest(path, read_manifest_value(path, None)?)
+}
+
+/// Reads a manifest from `path`, or from stdin when `path` is `-`. Without a
+/// `format` it is taken from the extension, stdin defaults to YAML.
+fn read_manifest_value(path: &str, format: Option<ManifestFormat>) -> Result<serde_yaml::Value> {
     let mut file_content = String::new();
-    File::open(path)?.read_to_string(&mut file_content)?;
-    let manifest = serde_yaml::from_str(&file_content)?;
+    if path == "-" {
+        std::io::stdin().read_to_string(&mut file_content)?;
+    } else {
+        File::open(path)?.read_to_string(&mut file_content)?;
+    }
+    let format = format.unwrap_or(if path.ends_with(".json") {
+        ManifestFormat::Json
+    } else {
+        ManifestFormat::Yaml
+    });
+    match format {
+        ManifestFormat::Yaml => Ok(serde_yaml::from_str(&file_content)?),
+        ManifestFormat::Json => {
+            let name = if path == "-" { "stdin" } else { path };
+            Ok(serde_json::from_str(&file_content).with_context(|| format!("{} is not valid JSON", name))?)
+        }
+    }
+}
+
+fn parse_manifest(path: &str, value: serde_yaml::Value) -> Result<Manifest> {
+    let version = match value.get("version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("{}: version must be a positive integer", path))?,
+    };
+    if version > MANIFEST_VERSION {
+        return Err(anyhow!(
+            "{} uses manifest version {}, but this molnctl only supports up to version {}. Please upgrade molnctl.",
+            path,
+            version,
+            MANIFEST_VERSION
+        ));
+    }
+
+    let manifest: Manifest = serde_yaml::from_value(value)?;
+    if let Some(schedule) = &manifest.service.schedule.0 {
+        check_schedule(schedule).with_context(|| format!("{}: service.schedule", path))?;
+    }
     Ok(manifest)
 }
 
+fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(|err| err.is_timeout())
+}
+
+/// Points the manifest at `env` and merges the matching
+/// `environments.<env>.service` section onto `service`. Precedence from low
+/// to high is: the manifest files, the environment section, --set and
+/// --image.
+fn apply_environment_overrides(manifest: &mut serde_yaml::Value, env: &str) -> Result<()> {
+    if !manifest.is_mapping() {
+        // Left for parse_manifest to report.
+        return Ok(());
+    }
+    manifest["environment"] = serde_yaml::Value::String(env.to_string());
+    let overrides = match manifest.get("environments").and_then(|envs| envs.get(env)) {
+        Some(overrides) => overrides.clone(),
+        None => return Ok(()),
+    };
+
+    let overrides = match overrides {
+        serde_yaml::Value::Mapping(overrides) => overrides,
+        _ => return Err(anyhow!("environments.{} must be a map", env)),
+    };
+    if let Some(key) = overrides.keys().find(|key| key.as_str() != Some("service")) {
+        return Err(anyhow!(
+            "environments.{} can only override service, found {}",
+            env,
+            serde_yaml::to_string(key)?.trim()
+        ));
+    }
+    let Some(service) = overrides.get("service") else {
+        return Ok(());
+    };
+    if let Some(name) = service.get("name") {
+        if Some(name) != manifest.get("service").and_then(|svc| svc.get("name")) {
+            return Err(anyhow!(
+                "environments.{} overrides service {}, which is not the service in the manifest",
+                env,
+                serde_yaml::to_string(name)?.trim()
+            ));
+        }
+    }
+    merge_manifest_values(&mut manifest["service"], service.clone());
+    Ok(())
+}
+
+/// Merges `overlay` onto `base`. Maps are merged key by key, anything else
+/// in the overlay, lists included, replaces what is in the base.
+fn merge_manifest_values(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) {
+    match (base, overlay) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_manifest_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Maps that take arbitrary keys, --set may add new entries to these.
+const OPEN_MANIFEST_FIELDS: &[&str] = &["env", "secrets"];
+
+/// Applies a `--set PATH=VALUE` override to a parsed manifest. Every segment
+/// of the path must already exist, except for new keys in `env` and `secrets`.
+fn set_manifest_field(manifest: &mut serde_yaml::Value, pair: &str) -> Result<()> {
+    let (path, value) = match pair.split_once('=') {
+        Some((path, value)) if !path.is_empty() => (path, value),
+        _ => {
+            return Err(CommandError::Usage(format!(
+                "Invalid --set '{}', expected PATH=VALUE",
+                pair
+            ))
+            .into())
+        }
+    };
+
+    let segments: Vec<&str> = path.split('.').collect();
+    let mut current = manifest;
+    for (i, segment) in segments.iter().enumerate() {
+        let is_last = i + 1 == segments.len();
+        let in_open_field = i > 0 && OPEN_MANIFEST_FIELDS.contains(&segments[i - 1]);
+        if in_open_field && current.is_null() {
+            *current = serde_yaml::Value::Mapping(Default::default());
+        }
+        let mapping = current.as_mapping_mut().ok_or_else(|| {
+            CommandError::Usage(format!(
+                "Invalid --set path {}: {} is not a map",
+                path,
+                segments[..i].join(".")
+            ))
+        })?;
+
+        let key = serde_yaml::Value::String(segment.to_string());
+        if !mapping.contains_key(&key) {
+            // New keys go into open maps, and an open map that is left out
+            // of the manifest is created on the way to one.
+            let creatable = if is_last { in_open_field } else { OPEN_MANIFEST_FIELDS.contains(segment) };
+            if creatable {
+                mapping.insert(key.clone(), serde_yaml::Value::Null);
+            } else {
+                return Err(CommandError::Usage(format!(
+                    "Invalid --set path {}: the manifest has no field {}",
+                    path,
+                    segments[..=i].join(".")
+                ))
+                .into());
+            }
+        }
+        current = mapping.get_mut(&key).expect("key is present");
+    }
+
+    *current = match current {
+        serde_yaml::Value::Number(_) => serde_yaml::Value::Number(value.parse().map_err(|_| {
+            CommandError::Usage(format!("Invalid --set value for {}: expected a number", path))
+        })?),
+        _ => serde_yaml::Value::String(value.to_string()),
+    };
+    Ok(())
+}
+
 fn write_manifest(path: &str, manifest: &Manifest) -> Result<()> {
     let mut file = File::create(path)?;
     let yaml = serde_yaml::to_string(manifest)?;
     file.write_all(yaml.as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::proxy::ProxyOptions;
+    use crate::api::tls::TlsOptions;
+    use crate::api::types::{
+        CreateEnvironmentResponse, ListOrganizationResponse, ListSecretsResponse, ListServicesResponse,
+        Organization, Secret,
+    };
+    use crate::config::user::{UserConfig, UserConfigLoader};
+    use std::sync::{Arc, Mutex};
+
+    /// An org `o` with a single environment `prod` holding `services` and
+    /// `secrets`. Mutating calls are recorded in `calls` instead of sent.
+    #[derive(Default)]
+    struct FakeApi {
+        services: Vec<Service>,
+        secrets: Vec<String>,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FakeApi {
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    /// The error returned by the API calls no test goes through.
+    fn unused(method: &str) -> anyhow::Error {
+        anyhow!("FakeApi::{} not used in these tests", method)
+    }
+
+    impl MolnettApi for FakeApi {
+        fn get_org(&self, _: &str, _: &str) -> Result<Organization> {
+            Err(unused("get_org"))
+        }
+
+        fn get_organizations(&self, _: &str) -> Result<ListOrganizationResponse> {
+            Err(unused("get_organizations"))
+        }
+
+        fn get_service(&self, _: &str, _: &str, _: &str, name: &str) -> Result<Option<Service>> {
+            Ok(self.services.iter().find(|svc| svc.name == name).cloned())
+        }
+
+        fn get_services(&self, _: &str, _: &str, _: &str) -> Result<ListServicesResponse> {
+            Ok(ListServicesResponse { services: self.services.clone() })
+        }
+
+        fn create_organization(&self, _: &str, _: &str, _: &str) -> Result<Organization> {
+            Err(unused("create_organization"))
+        }
+
+        fn get_environments(&self, _: &str, _: &str) -> Result<Vec<String>> {
+            Ok(vec!["prod".to_string()])
+        }
+
+        fn create_environment(
+            &self,
+            _: &str,
+            _: &str,
+            _: &str,
+            _: Option<&str>,
+        ) -> Result<CreateEnvironmentResponse> {
+            Err(unused("create_environment"))
+        }
+
+        fn delete_environment(&self, _: &str, _: &str, _: &str) -> Result<()> {
+            Err(unused("delete_environment"))
+        }
+
+        fn deploy_service(
+            &self,
+            _: &str,
+            _: &str,
+            env_name: &str,
+            service: Service,
+            _: Option<Duration>,
+        ) -> Result<Service> {
+            self.record(format!("deploy {} {} to {}", service.name, service.image, env_name));
+            Ok(service)
+        }
+
+        fn delete_service(&self, _: &str, _: &str, _: &str, name: &str) -> Result<()> {
+            self.record(format!("delete service {}", name));
+            Ok(())
+        }
+
+        fn get_secrets(&self, _: &str, _: &str, _: &str) -> Result<ListSecretsResponse> {
+            let secrets = self.secrets.iter().map(|name| Secret { name: name.clone() }).collect();
+            Ok(ListSecretsResponse { secrets })
+        }
+
+        fn create_secret(&self, _: &str, _: &str, _: &str, name: &str, _: &str) -> Result<()> {
+            self.record(format!("create secret {}", name));
+            Ok(())
+        }
+
+        fn delete_secret(&self, _: &str, _: &str, _: &str, name: &str) -> Result<()> {
+            self.record(format!("delete secret {}", name));
+            Ok(())
+        }
+    }
+
+    /// A logged in config with org `o`, stored in `dir`.
+    fn user_config(dir: &tempfile::TempDir) -> UserConfig {
+        let path = write_temp(
+            dir,
+            "config.json",
+            r#"{"token": {"access_token": "t", "expiry": "2099-01-01T00:00:00Z"}, "default_org": "o"}"#,
+        );
+        UserConfigLoader::load(&path.into()).unwrap()
+    }
+
+    /// Runs `deploy` against `api` and returns the calls it made.
+    fn run_deploy(api: FakeApi, deploy: Deploy) -> Result<Vec<String>> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = user_config(&dir);
+        let calls = api.calls.clone();
+        let base = CommandBase::new(&mut config, Box::new(api), None, TlsOptions::default(), ProxyOptions::default());
+        deploy.execute(&base)?;
+        let calls = calls.lock().unwrap().clone();
+        Ok(calls)
+    }
+
+    fn service(yaml: &str) -> Service {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const WEB: &str = "name: web\nimage: a:1\ncontainer_port: 80\n";
+
+    fn deploy_of(dir: &tempfile::TempDir, service: &str, no_confirm: bool) -> Deploy {
+        let service = service.lines().map(|line| format!("  {}\n", line)).collect::<String>();
+        Deploy {
+            manifest: write_temp(dir, "molnett.yaml", &format!("environment: prod\nservice:\n{}", service)),
+            confirm: ConfirmArgs { yes: no_confirm, no_confirm: None },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unchanged_service_is_not_deployed() {
+        let dir = tempfile::tempdir().unwrap();
+        let api = FakeApi { services: vec![service(WEB)], ..Default::default() };
+        // Explicit defaults and reordered fields are not a change either.
+        let manifest = "container_port: 80\nimage: a:1\nname: web\nrestart_policy: always\nenv: {}\n";
+        let calls = run_deploy(api, deploy_of(&dir, manifest, false)).unwrap();
+        assert!(calls.is_empty(), "{:?}", calls);
+    }
+
+    #[test]
+    fn changed_service_is_deployed() {
+        let dir = tempfile::tempdir().unwrap();
+        let api = FakeApi { services: vec![service(WEB)], ..Default::default() };
+        let calls = run_deploy(api, deploy_of(&dir, &WEB.replace("a:1", "a:2"), true)).unwrap();
+        assert_eq!(calls, vec!["deploy web a:2 to prod"]);
+    }
+
+    #[test]
+    fn new_service_is_deployed() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = run_deploy(FakeApi::default(), deploy_of(&dir, WEB, true)).unwrap();
+        assert_eq!(calls, vec!["deploy web a:1 to prod"]);
+    }
+
+    #[test]
+    fn only_and_exclude_select_the_service() {
+        let dir = tempfile::tempdir().unwrap();
+        let deploy = Deploy { exclude: vec!["web".to_string()], ..deploy_of(&dir, WEB, true) };
+        assert!(run_deploy(FakeApi::default(), deploy).unwrap().is_empty());
+
+        let deploy = Deploy { only: vec!["web".to_string()], ..deploy_of(&dir, WEB, true) };
+        assert_eq!(run_deploy(FakeApi::default(), deploy).unwrap(), vec!["deploy web a:1 to prod"]);
+
+        let deploy = Deploy { only: vec!["api".to_string()], ..deploy_of(&dir, WEB, true) };
+        let err = run_deploy(FakeApi::default(), deploy).unwrap_err();
+        assert_eq!(err.to_string(), "Service api is not in the manifest");
+    }
+
+    fn write_temp(dir: &tempfile::TempDir, name: &str, content: &str) -> String {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn read_env_file_parses_dotenv_syntax() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(
+            &dir,
+            ".env",
+            "# comment\n\nA=1\nexport B = two words \nC=\"quoted # not a comment\"\nD='single'\nE=\nF=a=b\nG=\"unbalanced\n",
+        );
+        let vars = read_env_file(&path).unwrap();
+        let expected = [
+            ("A", "1"),
+            ("B", "two words"),
+            ("C", "quoted # not a comment"),
+            ("D", "single"),
+            ("E", ""),
+            ("F", "a=b"),
+            ("G", "\"unbalanced"),
+        ];
+        assert_eq!(
+            vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn read_env_file_reports_the_bad_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(&dir, ".env", "A=1\nsecret-value\n");
+        let err = read_env_file(&path).unwrap_err().to_string();
+        assert_eq!(err, format!("{}:2: expected KEY=VALUE", path));
+
+        let path = write_temp(&dir, ".env", "=1\n");
+        assert!(read_env_file(&path).is_err());
+        assert!(read_env_file(&format!("{}/missing", dir.path().display())).is_err());
+    }
+
+    #[test]
+    fn read_manifest_rejects_future_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = |version: &str| {
+            let content = format!("{}environment: prod\nservice:\n  {}", version, WEB.replace('\n', "\n  "));
+            write_temp(&dir, "molnett.yaml", &content)
+        };
+        assert_eq!(read_manifest(&manifest("")).unwrap().version, None);
+        assert_eq!(read_manifest(&manifest("version: 1\n")).unwrap().version, Some(1));
+
+        let path = manifest("version: 2\n");
+        let err = read_manifest(&path).unwrap_err().to_string();
+        let expected = format!("{} uses manifest version 2, but this molnctl only supports up to version 1", path);
+        assert!(err.starts_with(&expected), "{}", err);
+        for version in ["-1", "'1'", "1.5"] {
+            let path = manifest(&format!("version: {}\n", version));
+            let err = read_manifest(&path).unwrap_err().to_string();
+            assert_eq!(err, format!("{}: version must be a positive integer", path));
+        }
+    }
+
+    fn numbered(changed: &[usize], len: usize) -> Vec<DiffLine> {
+        (0..len)
+            .map(|i| if changed.contains(&i) { DiffLine::Add(i.to_string()) } else { DiffLine::Same(i.to_string()) })
+            .collect()
+    }
+
+    #[test]
+    fn diff_lines_marks_added_and_removed_lines() {
+        use DiffLine::*;
+        assert_eq!(
+            diff_lines("a\nb\nc", "a\nx\nc"),
+            vec![Same("a".into()), Rem("b".into()), Add("x".into()), Same("c".into())]
+        );
+    }
+
+    #[test]
+    fn collapse_unchanged_keeps_context_around_changes() {
+        use DiffLine::*;
+        let same = |i: usize| Same(i.to_string());
+        let add = |i: usize| Add(i.to_string());
+        assert_eq!(
+            collapse_unchanged(numbered(&[5], 12), 2),
+            vec![Skipped(3), same(3), same(4), add(5), same(6), same(7), Skipped(4)]
+        );
+        // Runs between two changes keep context on both sides.
+        assert_eq!(
+            collapse_unchanged(numbered(&[0, 9], 10), 1),
+            vec![add(0), same(1), Skipped(6), same(8), add(9)]
+        );
+        // Nothing is skipped where the context covers the whole run.
+        assert_eq!(collapse_unchanged(numbered(&[0, 5], 6), 2), numbered(&[0, 5], 6));
+        assert_eq!(collapse_unchanged(numbered(&[2], 3), 0), vec![Skipped(2), add(2)]);
+        assert_eq!(collapse_unchanged(numbered(&[], 3), 2), vec![Skipped(3)]);
+    }
+
+    fn yaml(source: &str) -> serde_yaml::Value {
+        serde_yaml::from_str(source).unwrap()
+    }
+
+    const MANIFEST: &str = "environment: prod\nservice:\n  name: web\n  image: a:1\n  container_port: 80\n  env:\n    A: '1'\n";
+
+    #[test]
+    fn set_manifest_field_replaces_existing_fields() {
+        let mut manifest = yaml(MANIFEST);
+        set_manifest_field(&mut manifest, "service.image=a:2").unwrap();
+        set_manifest_field(&mut manifest, "service.container_port=8080").unwrap();
+        set_manifest_field(&mut manifest, "service.env.A=x=y").unwrap();
+        assert_eq!(manifest["service"]["image"], yaml("a:2"));
+        assert_eq!(manifest["service"]["container_port"], yaml("8080"));
+        assert_eq!(manifest["service"]["env"]["A"], yaml("x=y"));
+    }
+
+    #[test]
+    fn set_manifest_field_adds_env_and_secrets() {
+        let mut manifest = yaml(MANIFEST);
+        set_manifest_field(&mut manifest, "service.env.B=2").unwrap();
+        set_manifest_field(&mut manifest, "service.secrets.KEY=S1").unwrap();
+        assert_eq!(manifest["service"]["env"], yaml("{A: '1', B: '2'}"));
+        assert_eq!(manifest["service"]["secrets"], yaml("{KEY: S1}"));
+    }
+
+    #[test]
+    fn set_manifest_field_rejects_unknown_paths_and_bad_values() {
+        let cases = [
+            ("service.imag=a:2", "the manifest has no field service.imag"),
+            ("service.image.tag=1", "service.image is not a map"),
+            ("service.container_port=http", "expected a number"),
+            ("=x", "expected PATH=VALUE"),
+            ("service.image", "expected PATH=VALUE"),
+        ];
+        for (pair, reason) in cases {
+            let err = set_manifest_field(&mut yaml(MANIFEST), pair).unwrap_err().to_string();
+            assert!(err.contains(reason), "{}: {}", pair, err);
+        }
+    }
+
+    #[test]
+    fn prune_secrets_keeps_secrets_of_other_services() {
+        let dir = tempfile::tempdir().unwrap();
+        let api = FakeApi {
+            services: vec![service("name: worker\nimage: w:1\ncontainer_port: 80\nsecrets:\n  DB: S2\n")],
+            secrets: vec!["S1".to_string(), "S2".to_string(), "S3".to_string()],
+            ..Default::default()
+        };
+        let deploy = Deploy {
+            prune_secrets: true,
+            ..deploy_of(&dir, &format!("{}secrets:\n  KEY: S1\n", WEB), true)
+        };
+        let calls = run_deploy(api, deploy).unwrap();
+        assert_eq!(calls, vec!["deploy web a:1 to prod", "delete secret S3"]);
+    }
+
+    #[test]
+    fn merge_manifest_values_merges_maps_and_replaces_the_rest() {
+        let mut base = yaml(MANIFEST);
+        let overlay = yaml("service:\n  image: a:2\n  env:\n    B: '2'\n  command: [run]\n");
+        merge_manifest_values(&mut base, overlay);
+        assert_eq!(base["environment"], yaml("prod"));
+        assert_eq!(base["service"]["image"], yaml("a:2"));
+        assert_eq!(base["service"]["container_port"], yaml("80"));
+        assert_eq!(base["service"]["env"], yaml("{A: '1', B: '2'}"));
+        assert_eq!(base["service"]["command"], yaml("[run]"));
+
+        let mut base = yaml("args: [a, b]");
+        merge_manifest_values(&mut base, yaml("args: [c]"));
+        assert_eq!(base, yaml("args: [c]"));
+    }
+
+    const WITH_ENVIRONMENTS: &str = "environments:\n  staging:\n    service:\n      image: a:rc\n      env:\n        B: '2'\n";
+
+    #[test]
+    fn apply_environment_overrides_merges_the_environment_section() {
+        let mut manifest = yaml(&format!("{}{}", MANIFEST, WITH_ENVIRONMENTS));
+        apply_environment_overrides(&mut manifest, "staging").unwrap();
+        assert_eq!(manifest["environment"], yaml("staging"));
+        assert_eq!(manifest["service"]["image"], yaml("a:rc"));
+        assert_eq!(manifest["service"]["env"], yaml("{A: '1', B: '2'}"));
+
+        let mut manifest = yaml(&format!("{}{}", MANIFEST, WITH_ENVIRONMENTS));
+        apply_environment_overrides(&mut manifest, "dev").unwrap();
+        assert_eq!(manifest["environment"], yaml("dev"));
+        assert_eq!(manifest["service"]["image"], yaml("a:1"));
+    }
+
+    #[test]
+    fn apply_environment_overrides_rejects_invalid_sections() {
+        let cases = [
+            ("environments:\n  staging: [a]\n", "environments.staging must be a map"),
+            ("environments:\n  staging:\n    environment: prod\n", "can only override service, found environment"),
+            ("environments:\n  staging:\n    service:\n      name: api\n", "overrides service api, which is not"),
+        ];
+        for (environments, reason) in cases {
+            let mut manifest = yaml(&format!("{}{}", MANIFEST, environments));
+            let err = apply_environment_overrides(&mut manifest, "staging").unwrap_err().to_string();
+            assert!(err.contains(reason), "{}", err);
+        }
+    }
+
+    #[test]
+    fn set_overrides_win_over_the_environment_section() {
+        let mut manifest = yaml(&format!("{}{}", MANIFEST, WITH_ENVIRONMENTS));
+        apply_environment_overrides(&mut manifest, "staging").unwrap();
+        set_manifest_field(&mut manifest, "service.image=a:hotfix").unwrap();
+        let manifest = parse_manifest("molnett.yaml", manifest).unwrap();
+        assert_eq!(manifest.environment, "staging");
+        assert_eq!(manifest.service.image, "a:hotfix");
+    }
+
+    #[test]
+    fn wrap_line_breaks_after_spaces() {
+        assert_eq!(wrap_line("short", 10), vec!["short"]);
+        assert_eq!(wrap_line("exactly10!", 10), vec!["exactly10!"]);
+        assert_eq!(wrap_line("image: registry/org/web:1", 10), vec!["image: ", "registry/o", "rg/web:1"]);
+        assert_eq!(wrap_line("a b c d e f", 4), vec!["a b ", "c d ", "e f"]);
+        assert_eq!(wrap_line("", 4), vec![""]);
+    }
+
+    #[test]
+    fn wrap_line_counts_characters_not_bytes() {
+        assert_eq!(wrap_line("åäöåäö", 4), vec!["åäöå", "äö"]);
+        assert_eq!(wrap_line(" leading", 4), vec![" lea", "ding"]);
+    }
+
+    #[test]
+    fn yaml_and_json_manifests_parse_the_same() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml_path = write_temp(&dir, "molnett.yaml", MANIFEST);
+        let json = r#"{"environment": "prod", "service": {"name": "web", "image": "a:1", "container_port": 80, "env": {"A": "1"}}}"#;
+        let json_path = write_temp(&dir, "molnett.json", json);
+        // Without the extension the format has to be given.
+        let txt_path = write_temp(&dir, "manifest.txt", json);
+
+        let from_yaml = parse_manifest(&yaml_path, read_manifest_value(&yaml_path, None).unwrap()).unwrap();
+        let from_json = parse_manifest(&json_path, read_manifest_value(&json_path, None).unwrap()).unwrap();
+        let from_txt = read_manifest_value(&txt_path, Some(ManifestFormat::Json)).unwrap();
+        assert_eq!(from_yaml.environment, from_json.environment);
+        assert_eq!(from_yaml.service, from_json.service);
+        assert_eq!(parse_manifest(&txt_path, from_txt).unwrap().service, from_yaml.service);
+    }
+
+    #[test]
+    fn invalid_json_manifest_names_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(&dir, "molnett.json", "environment: prod");
+        let err = read_manifest_value(&path, None).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} is not valid JSON", path));
+    }
+
+    #[test]
+    fn manifest_without_environment_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(&dir, "molnett.yaml", "service:\n  name: web\n  image: a:1\n  container_port: 80\n");
+
+        let mut manifest = read_manifest(&path).unwrap();
+        assert_eq!(manifest.environment, "");
+        manifest.service.image = "a:2".to_string();
+        write_manifest(&path, &manifest).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(!written.contains("environment"));
+        assert_eq!(read_manifest(&path).unwrap().service.image, "a:2");
+    }
+}
@@ -1,4 +1,4 @@
-use ::config::ConfigError;
+use ::config::{ConfigError, FileFormat};
 use camino::{Utf8Path, Utf8PathBuf};
 use dirs_next::config_dir;
 use thiserror::Error;
@@ -9,6 +9,17 @@ pub mod user;
 pub enum Error {
     #[error("default config path not found")]
     NoDefaultConfigPath,
+    #[error("config at {path} is invalid: {detail}; run `molnctl config reset` or fix it manually")]
+    Invalid {
+        path: Utf8PathBuf,
+        detail: ConfigError,
+    },
+    #[error("context {0} does not exist, see `molnctl context list`")]
+    UnknownContext(String),
+    #[error("context {0} already exists")]
+    ContextExists(String),
+    #[error("context {0} is the current context, switch to another one with `molnctl context use` first")]
+    DeleteCurrentContext(String),
     #[error(transparent)]
     Config(#[from] ConfigError),
     #[error(transparent)]
@@ -29,7 +40,16 @@ pub fn default_user_config_path() -> Result<Utf8PathBuf, Error> {
     )?)
 }
 
-pub fn write_to_disk_json<T>(path: &Utf8Path, config: T) -> Result<(), Error>
+/// Config files are YAML when they have a `.yaml` or `.yml` extension and
+/// JSON otherwise.
+pub fn file_format(path: &Utf8Path) -> FileFormat {
+    match path.extension() {
+        Some("yaml") | Some("yml") => FileFormat::Yaml,
+        _ => FileFormat::Json,
+    }
+}
+
+pub fn write_to_disk<T>(path: &Utf8Path, config: T) -> Result<(), Error>
 where
     T: serde::Serialize,
 {
@@ -37,10 +57,101 @@ where
         std::fs::create_dir_all(parent_dir)?;
     }
 
-    let config_file = std::fs::File::create(path)?;
-    serde_json::to_writer_pretty(&config_file, &config)?;
+    // Write to a temporary file next to the config and rename it over the
+    // original, so a crash or full disk never leaves a truncated config. The
+    // temporary file gets a unique name and is removed again on failure.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_str().is_empty() => dir,
+        _ => Utf8Path::new("."),
+    };
+    let file_name = path.file_name().unwrap_or("config");
+    let tmp = tempfile::Builder::new()
+        .prefix(&format!(".{}.", file_name))
+        .suffix(".tmp")
+        .tempfile_in(dir)?;
+    // The temporary file is only readable by the user, a config that already
+    // exists keeps whatever permissions it had.
+    if let Ok(metadata) = std::fs::metadata(path) {
+        tmp.as_file().set_permissions(metadata.permissions())?;
+    }
+    match file_format(path) {
+        FileFormat::Yaml => serde_yaml::to_writer(tmp.as_file(), &config)?,
+        _ => serde_json::to_writer_pretty(tmp.as_file(), &config)?,
+    }
+    tmp.as_file().sync_all()?;
+    tmp.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
 
-    config_file.sync_all()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::ser::Error as _;
 
-    Ok(())
+    /// Fails halfway through serializing, like a full disk would.
+    struct Unserializable;
+
+    impl serde::Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(S::Error::custom("boom"))
+        }
+    }
+
+    fn config_path(dir: &tempfile::TempDir, name: &str) -> Utf8PathBuf {
+        Utf8PathBuf::try_from(dir.path().join(name)).unwrap()
+    }
+
+    fn dir_entries(dir: &tempfile::TempDir) -> Vec<String> {
+        let mut entries = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn failed_write_leaves_original_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_path(&dir, "config.json");
+        std::fs::write(&path, "{\"token\": \"x\"}").unwrap();
+
+        assert!(write_to_disk(&path, Unserializable).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"token\": \"x\"}");
+        assert_eq!(dir_entries(&dir), vec!["config.json"]);
+    }
+
+    #[test]
+    fn write_replaces_config_in_its_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = config_path(&dir, "config.json");
+        let yaml = config_path(&dir, "config.yaml");
+        std::fs::write(&json, "old").unwrap();
+
+        let value = serde_json::json!({ "token": "x" });
+        write_to_disk(&json, &value).unwrap();
+        write_to_disk(&yaml, &value).unwrap();
+        assert_eq!(std::fs::read_to_string(&json).unwrap(), "{\n  \"token\": \"x\"\n}");
+        assert_eq!(std::fs::read_to_string(&yaml).unwrap(), "token: x\n");
+        assert_eq!(dir_entries(&dir), vec!["config.json", "config.yaml"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Utf8Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let new = config_path(&dir, "new.json");
+        write_to_disk(&new, "x").unwrap();
+        assert_eq!(mode(&new), 0o600);
+
+        let existing = config_path(&dir, "existing.json");
+        std::fs::write(&existing, "old").unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o640)).unwrap();
+        write_to_disk(&existing, "x").unwrap();
+        assert_eq!(mode(&existing), 0o640);
+    }
 }
@@ -1,24 +1,112 @@
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use config::Config;
+use std::collections::BTreeMap;
 
 
 use crate::Cli;
 
-use super::{default_user_config_path, write_to_disk_json, Error};
+use super::{default_user_config_path, file_format, write_to_disk, Error};
 
 #[derive(Debug, Clone)]
 pub struct UserConfig {
     config: UserConfigInner,
     disk_config: UserConfigInner,
     path: Utf8PathBuf,
+    /// The context commands read from and write to.
+    context: String,
+    /// Whether a config from before contexts was moved into the `default`
+    /// context when it was loaded.
+    moved_to_context: bool,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct UserConfigInner {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current_context: Option<String>,
+    #[serde(default)]
+    contexts: BTreeMap<String, Context>,
+    // Settings from before contexts. They are moved into the `default`
+    // context on load and never written back.
+    #[serde(default, skip_serializing)]
     token: Option<Token>,
+    #[serde(default, skip_serializing)]
     default_org: Option<String>,
+    #[serde(default, skip_serializing)]
+    default_environment: Option<String>,
+    #[serde(default, skip_serializing)]
+    url: Option<String>,
+}
+
+/// A named set of url, credentials and defaults, like a kubectl context.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Context {
     #[serde(default = "default_url")]
     url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<Token>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_org: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_environment: Option<String>,
+}
+
+impl Context {
+    fn new(url: String) -> Self {
+        Context {
+            url,
+            token: None,
+            default_org: None,
+            default_environment: None,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn default_org(&self) -> Option<&str> {
+        self.default_org.as_deref()
+    }
+}
+
+pub const DEFAULT_CONTEXT: &str = "default";
+
+impl UserConfigInner {
+    fn new() -> Self {
+        let mut contexts = BTreeMap::new();
+        contexts.insert(DEFAULT_CONTEXT.to_string(), Context::new(default_url()));
+        UserConfigInner {
+            current_context: Some(DEFAULT_CONTEXT.to_string()),
+            contexts,
+            token: None,
+            default_org: None,
+            default_environment: None,
+            url: None,
+        }
+    }
+
+    /// Moves the settings of a config from before contexts into the
+    /// `default` context. Returns whether there was anything to move.
+    fn move_to_context(&mut self) -> bool {
+        if !self.contexts.is_empty() {
+            return false;
+        }
+        let legacy = self.token.is_some()
+            || self.default_org.is_some()
+            || self.default_environment.is_some()
+            || self.url.is_some();
+        self.contexts.insert(
+            DEFAULT_CONTEXT.to_string(),
+            Context {
+                url: self.url.take().unwrap_or_else(default_url),
+                token: self.token.take(),
+                default_org: self.default_org.take(),
+                default_environment: self.default_environment.take(),
+            },
+        );
+        self.current_context.get_or_insert_with(|| DEFAULT_CONTEXT.to_string());
+        legacy
+    }
 }
 
 fn default_url() -> String {
@@ -46,28 +134,75 @@ pub struct UserConfigLoader {
 }
 
 impl UserConfig {
-    pub fn new(cli: &Cli) -> Self {
-        let config_path = match &cli.config {
-            Some(path) => path.clone(),
-            None => default_user_config_path()
-                .expect("No config path provided and default path not found"),
-        };
-        let mut config =
-            UserConfigLoader::load(&config_path).expect("Loading config from disk failed");
+    pub fn new(cli: &Cli) -> Result<Self, Error> {
+        let config_path = Self::path(cli)?;
+        let mut config = UserConfigLoader::load(&config_path)?;
 
         // TODO: write config to disk after reading so it gets written if it doesn't exist
 
+        if let Some(context) = &cli.context {
+            if !config.config.contexts.contains_key(context) {
+                return Err(Error::UnknownContext(context.clone()));
+            }
+            config.context = context.clone();
+        }
+
         if let Some(h) = &cli.url {
             config.set_url(h.to_string());
         }
 
-        config
+        Ok(config)
+    }
+    pub fn path(cli: &Cli) -> Result<Utf8PathBuf, Error> {
+        match &cli.config {
+            Some(path) => Ok(path.clone()),
+            None => default_user_config_path(),
+        }
+    }
+    /// Moves the config at `path` out of the way and writes a fresh default
+    /// config in its place. Returns where the old config was moved to.
+    pub fn reset(path: &Utf8Path) -> Result<Option<Utf8PathBuf>, Error> {
+        let backup = if path.exists() {
+            let backup = Utf8PathBuf::from(format!(
+                "{}.{}.bak",
+                path,
+                chrono::Utc::now().format("%Y%m%d%H%M%S")
+            ));
+            std::fs::rename(path, &backup)?;
+            Some(backup)
+        } else {
+            None
+        };
+
+        write_to_disk(path, UserConfigInner::new())?;
+        Ok(backup)
+    }
+    fn active(&self) -> &Context {
+        &self.config.contexts[&self.context]
+    }
+    /// Applies `update` to the active context both in memory and on disk,
+    /// then writes the config.
+    fn update_active(&mut self, update: impl Fn(&mut Context)) -> Result<(), super::Error> {
+        let url = self.active().url.clone();
+        update(self.config.contexts.get_mut(&self.context).unwrap());
+        // The in memory context can have its url overridden by --url, which
+        // must not end up on disk.
+        update(
+            self.disk_config
+                .contexts
+                .entry(self.context.clone())
+                .or_insert_with(|| Context::new(url)),
+        );
+        write_to_disk(&self.path, &self.disk_config)
     }
     pub fn get_token(&self) -> Option<&str> {
-        self.config.token.as_ref().map(|u| u.access_token.as_str())
+        self.active().token.as_ref().map(|u| u.access_token.as_str())
+    }
+    pub fn get_token_expiry(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.active().token.as_ref().and_then(|token| token.expiry)
     }
     pub fn is_token_expired(&self) -> bool {
-        if let Some(token) = &self.config.token {
+        if let Some(token) = &self.active().token {
             if let Some(expiry) = token.expiry {
                 return expiry < chrono::Utc::now();
             }
@@ -75,43 +210,279 @@ impl UserConfig {
         true
     }
     pub fn write_token(&mut self, token: Token) -> Result<(), super::Error> {
-        self.disk_config.token = Some(token.clone());
-        self.config.token = Some(token);
-
-        write_to_disk_json(&self.path, &self.disk_config)
+        self.update_active(|context| context.token = Some(token.clone()))
     }
     pub fn write_default_org(&mut self, org_name: String) -> Result<(), super::Error> {
-        self.disk_config.default_org = Some(org_name.clone());
-        self.config.default_org = Some(org_name);
-        write_to_disk_json(&self.path, &self.disk_config)
+        self.update_active(|context| context.default_org = Some(org_name.clone()))
     }
     pub fn get_default_org(&self) -> Option<&str> {
-        self.config.default_org.as_deref()
+        self.active().default_org.as_deref()
+    }
+    pub fn write_default_environment(&mut self, env_name: String) -> Result<(), super::Error> {
+        self.update_active(|context| context.default_environment = Some(env_name.clone()))
+    }
+    pub fn get_default_environment(&self) -> Option<&str> {
+        self.active().default_environment.as_deref()
     }
     pub fn get_url(&self) -> &str {
-        self.config.url.as_ref()
+        self.active().url.as_ref()
+    }
+    pub fn is_default_url(&self) -> bool {
+        self.active().url == default_url()
     }
     fn set_url(&mut self, url: String) {
-        self.config.url = url;
+        self.config.contexts.get_mut(&self.context).unwrap().url = url;
+    }
+    pub fn get_path(&self) -> &Utf8PathBuf {
+        &self.path
+    }
+    pub fn get_context(&self) -> &str {
+        &self.context
+    }
+    pub fn get_current_context(&self) -> Option<&str> {
+        self.disk_config.current_context.as_deref()
+    }
+    pub fn contexts(&self) -> impl Iterator<Item = (&str, &Context)> {
+        self.disk_config.contexts.iter().map(|(name, context)| (name.as_str(), context))
+    }
+    /// Adds a context talking to `url`, without credentials or defaults.
+    pub fn create_context(&mut self, name: &str, url: String) -> Result<(), super::Error> {
+        if self.disk_config.contexts.contains_key(name) {
+            return Err(Error::ContextExists(name.to_string()));
+        }
+        let context = Context::new(url);
+        self.config.contexts.insert(name.to_string(), context.clone());
+        self.disk_config.contexts.insert(name.to_string(), context);
+        write_to_disk(&self.path, &self.disk_config)
+    }
+    pub fn use_context(&mut self, name: &str) -> Result<(), super::Error> {
+        if !self.disk_config.contexts.contains_key(name) {
+            return Err(Error::UnknownContext(name.to_string()));
+        }
+        self.disk_config.current_context = Some(name.to_string());
+        self.config.current_context = Some(name.to_string());
+        write_to_disk(&self.path, &self.disk_config)
+    }
+    /// Removes a context. The current context can't be deleted, switch to
+    /// another one first.
+    pub fn delete_context(&mut self, name: &str) -> Result<(), super::Error> {
+        if !self.disk_config.contexts.contains_key(name) {
+            return Err(Error::UnknownContext(name.to_string()));
+        }
+        if self.get_current_context() == Some(name) {
+            return Err(Error::DeleteCurrentContext(name.to_string()));
+        }
+        self.disk_config.contexts.remove(name);
+        self.config.contexts.remove(name);
+        write_to_disk(&self.path, &self.disk_config)
+    }
+    /// Brings the config on disk up to date with the current format and
+    /// returns a description of every change made. Running it again on an
+    /// already migrated config changes nothing.
+    pub fn migrate(&mut self) -> Result<Vec<String>, super::Error> {
+        let mut changes = Vec::new();
+
+        if self.moved_to_context {
+            changes.push(format!("moved the settings into context {:?}", DEFAULT_CONTEXT));
+            self.moved_to_context = false;
+        }
+
+        for (name, context) in self.disk_config.contexts.iter_mut() {
+            let url = context.url.trim().trim_end_matches('/').to_string();
+            if url != context.url {
+                changes.push(format!("{}: url: {:?} -> {:?}", name, context.url, url));
+                context.url = url;
+            }
+        }
+
+        if !changes.is_empty() {
+            write_to_disk(&self.path, &self.disk_config)?;
+        }
+        Ok(changes)
     }
 }
 
 impl UserConfigLoader {
     pub fn load(path: &Utf8PathBuf) -> Result<UserConfig, Error> {
+        let invalid = |detail| Error::Invalid {
+            path: path.clone(),
+            detail,
+        };
         let disk_config = Config::builder()
             .add_source(
                 config::File::with_name(path.as_str())
-                    .format(config::FileFormat::Json)
+                    .format(file_format(path))
                     .required(false),
             )
-            .build()?;
+            .build()
+            .map_err(invalid)?;
 
         let config = Config::builder().add_source(disk_config.clone()).build()?;
 
+        let mut config: UserConfigInner = config.try_deserialize().map_err(invalid)?;
+        let mut disk_config: UserConfigInner = disk_config.try_deserialize().map_err(invalid)?;
+        config.move_to_context();
+        let moved_to_context = disk_config.move_to_context();
+
+        let context = disk_config
+            .current_context
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CONTEXT.to_string());
+        // A current context that was removed by hand reads as logged out
+        // rather than failing every command.
+        config
+            .contexts
+            .entry(context.clone())
+            .or_insert_with(|| Context::new(default_url()));
+
         Ok(UserConfig {
-            config: config.try_deserialize()?,
-            disk_config: disk_config.try_deserialize()?,
+            config,
+            disk_config,
             path: path.clone(),
+            context,
+            moved_to_context,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn config_path(dir: &tempfile::TempDir, name: &str) -> Utf8PathBuf {
+        Utf8PathBuf::try_from(dir.path().join(name)).unwrap()
+    }
+
+    fn load(path: &Utf8Path, flags: &[&str]) -> Result<UserConfig, Error> {
+        let args = ["molnctl", "--config", path.as_str()]
+            .into_iter()
+            .chain(flags.iter().copied())
+            .chain(["context", "current"]);
+        UserConfig::new(&Cli::try_parse_from(args).unwrap())
+    }
+
+    const LEGACY: &str = r#"{"token": {"access_token": "t"}, "default_org": "o", "url": "https://api.example.com"}"#;
+
+    #[test]
+    fn legacy_config_moves_into_the_default_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_path(&dir, "config.json");
+        std::fs::write(&path, LEGACY).unwrap();
+
+        let mut config = load(&path, &[]).unwrap();
+        assert_eq!(config.get_context(), DEFAULT_CONTEXT);
+        assert_eq!(config.get_token(), Some("t"));
+        assert_eq!(config.get_default_org(), Some("o"));
+        assert_eq!(config.get_url(), "https://api.example.com");
+
+        assert_eq!(config.migrate().unwrap().len(), 1);
+        let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["current_context"], "default");
+        assert_eq!(written["contexts"]["default"]["default_org"], "o");
+        assert!(written.get("default_org").is_none() && written.get("token").is_none());
+        assert!(config.migrate().unwrap().is_empty());
+    }
+
+    #[test]
+    fn switching_contexts_is_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_path(&dir, "config.json");
+        std::fs::write(&path, LEGACY).unwrap();
+
+        let mut config = load(&path, &[]).unwrap();
+        config.create_context("staging", "https://staging.example.com".to_string()).unwrap();
+        assert!(matches!(
+            config.create_context("staging", "x".to_string()),
+            Err(Error::ContextExists(_))
+        ));
+        config.use_context("staging").unwrap();
+        assert!(matches!(config.use_context("nope"), Err(Error::UnknownContext(_))));
+
+        let config = load(&path, &[]).unwrap();
+        assert_eq!(config.get_context(), "staging");
+        assert_eq!(config.get_url(), "https://staging.example.com");
+        assert_eq!(config.get_token(), None);
+        let names = config.contexts().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["default", "staging"]);
+    }
+
+    #[test]
+    fn flags_take_precedence_over_the_current_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_path(&dir, "config.json");
+        std::fs::write(&path, LEGACY).unwrap();
+        let mut config = load(&path, &[]).unwrap();
+        config.create_context("staging", "https://staging.example.com".to_string()).unwrap();
+        config.use_context("staging").unwrap();
+
+        let config = load(&path, &["--context", "default"]).unwrap();
+        assert_eq!(config.get_context(), "default");
+        assert_eq!(config.get_token(), Some("t"));
+        // --context only applies to the one command.
+        assert_eq!(config.get_current_context(), Some("staging"));
+
+        let mut config = load(&path, &["--url", "http://localhost:8080"]).unwrap();
+        assert_eq!(config.get_url(), "http://localhost:8080");
+        config.write_default_org("o2".to_string()).unwrap();
+        let config = load(&path, &[]).unwrap();
+        assert_eq!(config.get_url(), "https://staging.example.com");
+        assert_eq!(config.get_default_org(), Some("o2"));
+
+        assert!(matches!(load(&path, &["--context", "nope"]), Err(Error::UnknownContext(_))));
+    }
+
+    #[test]
+    fn current_context_cannot_be_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_path(&dir, "config.json");
+        let mut config = load(&path, &[]).unwrap();
+        config.create_context("staging", "https://staging.example.com".to_string()).unwrap();
+
+        assert!(matches!(
+            config.delete_context(DEFAULT_CONTEXT),
+            Err(Error::DeleteCurrentContext(_))
+        ));
+        config.delete_context("staging").unwrap();
+        assert_eq!(load(&path, &[]).unwrap().contexts().count(), 1);
+    }
+
+    fn round_trip(name: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_path(&dir, name);
+        let mut config = load(&path, &[]).unwrap();
+        config
+            .write_token(Token {
+                access_token: "t".to_string(),
+                refresh_token: Some("r".to_string()),
+                expiry: Some("2099-01-01T00:00:00Z".parse().unwrap()),
+            })
+            .unwrap();
+        config.write_default_org("o".to_string()).unwrap();
+        config.write_default_environment("prod".to_string()).unwrap();
+
+        let config = load(&path, &[]).unwrap();
+        assert_eq!(config.get_token(), Some("t"));
+        assert!(!config.is_token_expired());
+        assert_eq!(config.get_default_org(), Some("o"));
+        assert_eq!(config.get_default_environment(), Some("prod"));
+        std::fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn json_config_round_trips() {
+        let written = round_trip("config.json");
+        let value: serde_json::Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(value["contexts"]["default"]["default_environment"], "prod");
+    }
+
+    #[test]
+    fn yaml_config_round_trips() {
+        for name in ["config.yaml", "config.yml"] {
+            let written = round_trip(name);
+            let value: serde_yaml::Value = serde_yaml::from_str(&written).unwrap();
+            assert_eq!(value["contexts"]["default"]["default_environment"], "prod");
+            assert!(serde_json::from_str::<serde_json::Value>(&written).is_err());
+        }
+    }
+}
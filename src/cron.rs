@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+
+/// Shorthands accepted in place of the five fields.
+const MACROS: &[&str] = &["@yearly", "@annually", "@monthly", "@weekly", "@daily", "@midnight", "@hourly"];
+
+const MONTHS: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+}
+
+const FIELDS: [Field; 5] = [
+    Field { name: "minute", min: 0, max: 59, names: &[] },
+    Field { name: "hour", min: 0, max: 23, names: &[] },
+    Field { name: "day of month", min: 1, max: 31, names: &[] },
+    Field { name: "month", min: 1, max: 12, names: MONTHS },
+    // 7 is Sunday as well, like in most cron implementations.
+    Field { name: "day of week", min: 0, max: 7, names: WEEKDAYS },
+];
+
+/// Checks that `schedule` is a standard five field cron expression, e.g.
+/// `*/15 * * * *` or `0 3 * * mon-fri`, or one of the `@daily` style macros.
+pub fn check_schedule(schedule: &str) -> Result<()> {
+    let invalid = |reason: String| anyhow!("Invalid schedule '{}': {}", schedule, reason);
+
+    let schedule = schedule.trim();
+    if schedule.starts_with('@') {
+        if MACROS.contains(&schedule) {
+            return Ok(());
+        }
+        return Err(invalid(format!("expected one of {}", MACROS.join(", "))));
+    }
+
+    let parts = schedule.split_whitespace().collect::<Vec<_>>();
+    if parts.len() != FIELDS.len() {
+        return Err(invalid(format!(
+            "expected 5 fields (minute hour day-of-month month day-of-week), got {}",
+            parts.len()
+        )));
+    }
+    for (part, field) in parts.iter().zip(FIELDS.iter()) {
+        check_field(part, field).map_err(|reason| invalid(format!("{} '{}' {}", field.name, part, reason)))?;
+    }
+    Ok(())
+}
+
+fn check_field(part: &str, field: &Field) -> std::result::Result<(), String> {
+    for item in part.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (item, None),
+        };
+        if let Some(step) = step {
+            match step.parse::<u32>() {
+                Ok(step) if step > 0 => {}
+                _ => return Err(format!("has an invalid step '{}'", step)),
+            }
+        }
+        if range == "*" {
+            continue;
+        }
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (value(start, field)?, value(end, field)?),
+            None => {
+                let value = value(range, field)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            return Err(format!("has a range that ends before it starts '{}'", range));
+        }
+    }
+    Ok(())
+}
+
+fn value(value: &str, field: &Field) -> std::result::Result<u32, String> {
+    let lower = value.to_ascii_lowercase();
+    if let Some(idx) = field.names.iter().position(|name| *name == lower) {
+        return Ok(idx as u32 + field.min);
+    }
+    match value.parse::<u32>() {
+        Ok(number) if (field.min..=field.max).contains(&number) => Ok(number),
+        _ => Err(format!("must be between {} and {}", field.min, field.max)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_schedules() {
+        for schedule in [
+            "* * * * *",
+            "*/15 * * * *",
+            "0 3 * * mon-fri",
+            "0 0 1 jan,jul *",
+            "5,35 8-18/2 * * 1-5",
+            "0 0 * * 7",
+            "0 0 31 DEC SUN",
+            " 0 12 * * * ",
+            "@daily",
+            "@hourly",
+        ] {
+            assert!(check_schedule(schedule).is_ok(), "{}", schedule);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        let cases = [
+            ("@every 5m", "expected one of @yearly"),
+            ("@often", "expected one of @yearly"),
+            ("* * * *", "expected 5 fields"),
+            ("0 0 * * * *", "got 6"),
+            ("60 * * * *", "minute '60' must be between 0 and 59"),
+            ("* 24 * * *", "hour '24' must be between 0 and 23"),
+            ("* * 0 * *", "day of month '0' must be between 1 and 31"),
+            ("* * * 13 *", "month '13' must be between 1 and 12"),
+            ("* * * * 8", "day of week '8' must be between 0 and 7"),
+            ("* * * * fri-mon", "ends before it starts"),
+            ("*/0 * * * *", "invalid step '0'"),
+            ("*/x * * * *", "invalid step 'x'"),
+            ("1-x * * * *", "must be between"),
+            ("* * * foo *", "month 'foo' must be between"),
+        ];
+        for (schedule, reason) in cases {
+            let err = check_schedule(schedule).unwrap_err().to_string();
+            assert!(err.starts_with(&format!("Invalid schedule '{}'", schedule)), "{}", err);
+            assert!(err.contains(reason), "{}: {}", schedule, err);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{anyhow, Result};
+use dialoguer::console::style;
+
+/// A parsed container image reference, `[registry/]repository[:tag][@digest]`,
+/// following the grammar of the Docker distribution reference.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ImageRef<'a> {
+    pub registry: Option<&'a str>,
+    pub repository: &'a str,
+    pub tag: Option<&'a str>,
+    pub digest: Option<&'a str>,
+}
+
+impl<'a> ImageRef<'a> {
+    pub fn parse(reference: &'a str) -> Result<Self> {
+        let invalid = |reason: &str| anyhow!("Invalid image reference '{}': {}", reference, reason);
+
+        let (name, digest) = match reference.split_once('@') {
+            Some((name, digest)) => {
+                if !is_digest(digest) {
+                    return Err(invalid("the digest must look like sha256:<hex>"));
+                }
+                (name, Some(digest))
+            }
+            None => (reference, None),
+        };
+
+        let (name, tag) = match name.rfind(':') {
+            Some(idx) if !name[idx..].contains('/') => (&name[..idx], Some(&name[idx + 1..])),
+            _ => (name, None),
+        };
+        if let Some(tag) = tag {
+            if !is_tag(tag) {
+                return Err(invalid("the tag may only contain letters, digits, '_', '.' and '-', and at most 128 of them"));
+            }
+        }
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((first, rest)) if first.contains(['.', ':']) || first == "localhost" => (Some(first), rest),
+            _ => (None, name),
+        };
+        if let Some(registry) = registry {
+            if !is_registry(registry) {
+                return Err(invalid("the registry must be a host name with an optional port"));
+            }
+        }
+        if repository.is_empty() {
+            return Err(invalid("the repository name is missing"));
+        }
+        if let Some(component) = repository.split('/').find(|c| !is_path_component(c)) {
+            return Err(if component.is_empty() {
+                invalid("the repository has an empty path component")
+            } else {
+                invalid(&format!(
+                    "'{}' must be lowercase letters and digits, separated by '.', '_' or '-'",
+                    component
+                ))
+            });
+        }
+
+        Ok(ImageRef {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+}
+
+fn is_digest(digest: &str) -> bool {
+    match digest.split_once(':') {
+        Some((algorithm, hex)) => {
+            !algorithm.is_empty()
+                && algorithm.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && hex.len() >= 32
+                && hex.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+        }
+        None => false,
+    }
+}
+
+fn is_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= 128
+        && !tag.starts_with(['.', '-'])
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+}
+
+fn is_registry(registry: &str) -> bool {
+    let (host, port) = match registry.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (registry, None),
+    };
+    let host_ok = !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    let port_ok = match port {
+        Some(port) => !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()),
+        None => true,
+    };
+    host_ok && port_ok
+}
+
+fn is_path_component(component: &str) -> bool {
+    let alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    component.starts_with(alnum)
+        && component.ends_with(alnum)
+        && component.chars().all(|c| alnum(c) || "._-".contains(c))
+}
+
+/// Rejects malformed image references and warns when one has no tag, since
+/// the registry then silently falls back to `latest`.
+pub fn check_image(reference: &str) -> Result<()> {
+    let image = ImageRef::parse(reference)?;
+    if image.tag.is_none() && image.digest.is_none() {
+        eprintln!(
+            "{} image {} has no tag, latest will be used",
+            style("warning:").yellow().bold().for_stderr(),
+            reference
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:4f53cda18c2baa0c0354bb5f9a3ecbe5ed12ab4d8e11ba873c2f11161202b945";
+
+    #[test]
+    fn parses_valid_references() {
+        assert_eq!(
+            ImageRef::parse("nginx").unwrap(),
+            ImageRef { registry: None, repository: "nginx", tag: None, digest: None }
+        );
+        assert_eq!(
+            ImageRef::parse("library/nginx:1.25-alpine").unwrap(),
+            ImageRef { registry: None, repository: "library/nginx", tag: Some("1.25-alpine"), digest: None }
+        );
+        assert_eq!(
+            ImageRef::parse("oci.se-ume.mltt.art/org-1/web:v1.2.3").unwrap(),
+            ImageRef {
+                registry: Some("oci.se-ume.mltt.art"),
+                repository: "org-1/web",
+                tag: Some("v1.2.3"),
+                digest: None,
+            }
+        );
+        assert_eq!(
+            ImageRef::parse("localhost:5000/web").unwrap(),
+            ImageRef { registry: Some("localhost:5000"), repository: "web", tag: None, digest: None }
+        );
+        assert_eq!(
+            ImageRef::parse("localhost/my_app.v2").unwrap(),
+            ImageRef { registry: Some("localhost"), repository: "my_app.v2", tag: None, digest: None }
+        );
+    }
+
+    #[test]
+    fn parses_digests() {
+        let reference = format!("registry.example.com:443/web@{}", DIGEST);
+        assert_eq!(
+            ImageRef::parse(&reference).unwrap(),
+            ImageRef {
+                registry: Some("registry.example.com:443"),
+                repository: "web",
+                tag: None,
+                digest: Some(DIGEST),
+            }
+        );
+        let reference = format!("web:1.0@{}", DIGEST);
+        let image = ImageRef::parse(&reference).unwrap();
+        assert_eq!((image.tag, image.digest), (Some("1.0"), Some(DIGEST)));
+    }
+
+    #[test]
+    fn rejects_malformed_references() {
+        let cases = [
+            ("oci.se-ume.mltt.art//name:tag", "empty path component"),
+            ("oci.se-ume.mltt.art/", "repository name is missing"),
+            ("", "repository name is missing"),
+            ("Web:1", "'Web' must be lowercase"),
+            ("org/-web", "'-web' must be lowercase"),
+            ("web:", "the tag may only contain"),
+            ("web:.hidden", "the tag may only contain"),
+            ("web:a+b", "the tag may only contain"),
+            ("web@sha256:abc", "the digest must look like"),
+            ("web@4f53cda18c2baa0c0354bb5f9a3ecbe5", "the digest must look like"),
+            ("bad_host.com/web", "the registry must be a host name"),
+            ("host.com:port/web", "the registry must be a host name"),
+        ];
+        for (reference, reason) in cases {
+            let err = ImageRef::parse(reference).unwrap_err().to_string();
+            assert!(err.contains(reason), "{}: {}", reference, err);
+        }
+        let long_tag = format!("web:{}", "a".repeat(129));
+        assert!(ImageRef::parse(&long_tag).is_err());
+    }
+
+    #[test]
+    fn check_image_allows_missing_tag() {
+        assert!(check_image("web").is_ok());
+        assert!(check_image("web:").is_err());
+    }
+}
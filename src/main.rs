@@ -1,11 +1,21 @@
+use crate::api::{
+    cache::ResponseCache, dry_run::DryRunClient, proxy::ProxyOptions, tls::TlsOptions, APIClient,
+    ApiError, MolnettApi,
+};
 use crate::config::user::UserConfig;
 use anyhow::Result;
 use camino::Utf8PathBuf;
-use clap::{Parser, Subcommand};
-use commands::CommandBase;
+use clap::builder::BoolishValueParser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use commands::{Cancelled, CommandBase, CommandError};
+use dialoguer::console::{self, style};
+use reqwest::StatusCode;
 mod api;
 mod commands;
 mod config;
+mod cron;
+mod image;
+mod util;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -35,6 +45,14 @@ pub struct Cli {
     )]
     url: Option<String>,
 
+    #[arg(
+        global = true,
+        long,
+        env("MOLNETT_CONTEXT"),
+        help = "Context to use (overrides the current context in config)"
+    )]
+    context: Option<String>,
+
     #[arg(
         global = true,
         long,
@@ -43,20 +61,100 @@ pub struct Cli {
     )]
     org: Option<String>,
 
+    #[arg(
+        global = true,
+        long,
+        help = "Print what would be changed without creating, updating or deleting anything"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        global = true,
+        long,
+        help = "Don't verify the API's TLS certificate. Requires MOLNETT_ALLOW_INSECURE=1"
+    )]
+    insecure: bool,
+
+    #[arg(
+        global = true,
+        long,
+        value_name = "FILE",
+        help = "PEM encoded CA certificate to trust in addition to the system roots"
+    )]
+    ca_cert: Option<Utf8PathBuf>,
+
+    #[arg(
+        global = true,
+        long,
+        value_name = "URL",
+        help = "Proxy to connect to the API through. Default is taken from HTTPS_PROXY, hosts in NO_PROXY bypass it"
+    )]
+    proxy: Option<String>,
+
+    #[arg(
+        global = true,
+        long,
+        value_enum,
+        default_value_t,
+        help = "When to use colors. auto uses them on terminals unless NO_COLOR is set"
+    )]
+    color: ColorChoice,
+
+    #[arg(
+        global = true,
+        long,
+        env("MOLNETT_JSON_ERRORS"),
+        value_parser = BoolishValueParser::new(),
+        help = "Print errors to stderr as JSON with their kind and exit code"
+    )]
+    json_errors: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Colors are written through `console`, which keeps the choice in a
+    /// global so every command picks it up.
+    fn apply(self) {
+        let enabled = match self {
+            ColorChoice::Auto if std::env::var_os("NO_COLOR").is_some() => false,
+            ColorChoice::Auto => return,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        };
+        console::set_colors_enabled(enabled);
+        console::set_colors_enabled_stderr(enabled);
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// Login to Molnett
     Auth(commands::auth::Auth),
+    /// Print or install shell completions
+    Completions(commands::completions::Completions),
+    /// Manage the molnctl config file
+    Config(commands::config::Config),
+    /// Switch between Molnett urls and accounts
+    Context(commands::context::Context),
     /// Create and manage environments
     Environments(commands::environments::Environments),
     /// Deploy a service
     Deploy(commands::services::Deploy),
     /// Tail logs from a service
     Logs(commands::services::Logs),
+    /// Write man pages for every command
+    #[command(hide = true)]
+    GenerateMan(commands::man::GenerateMan),
     /// Generate Dockerfile and Molnett manifest
     Initialize(commands::services::Initialize),
     /// Manage organizations
@@ -65,27 +163,192 @@ enum Commands {
     Secrets(commands::secrets::Secrets),
     /// Deploy and manage services
     Services(commands::services::Services),
+    /// Build, push and deploy a service in one go
+    Up(commands::services::Up),
+}
+
+fn main() {
+    // Prompts hide the cursor while active, make sure it comes back when the
+    // user bails out with Ctrl-C.
+    ctrlc::set_handler(|| {
+        restore_terminal();
+        std::process::exit(130);
+    })
+    .expect("Failed to set Ctrl-C handler");
+
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    cli.color.apply();
+    let json_errors = cli.json_errors;
+
+    if let Err(err) = run(cli, &matches) {
+        if err.is::<Cancelled>() {
+            restore_terminal();
+        }
+        if json_errors {
+            print_json_error(&err);
+        } else if err.is::<Cancelled>() {
+            eprintln!("Cancelled");
+        } else {
+            print_error(&err);
+        }
+        std::process::exit(exit_code(&err));
+    }
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+fn print_error(err: &anyhow::Error) {
+    eprintln!("{} {}", style("error:").red().bold().for_stderr(), err);
+    for cause in err.chain().skip(1) {
+        eprintln!("  {} {}", style("caused by:").red().for_stderr(), cause);
+    }
+    if let Some(hint) = error_hint(err) {
+        eprintln!("{} {}", style("hint:").cyan().for_stderr(), hint);
+    }
+}
+
+fn print_json_error(err: &anyhow::Error) {
+    let json = serde_json::json!({
+        "error": err.to_string(),
+        "causes": err.chain().skip(1).map(|cause| cause.to_string()).collect::<Vec<_>>(),
+        "kind": error_kind(err),
+        "exit_code": exit_code(err),
+        "hint": error_hint(err),
+    });
+    eprintln!("{}", json);
+}
+
+fn error_hint(err: &anyhow::Error) -> Option<&'static str> {
+    match exit_code(err) {
+        3 => Some("run `molnctl auth login` to log in again"),
+        5 => Some("check your network connection and the API url (--url)"),
+        _ => None,
+    }
+}
+
+fn restore_terminal() {
+    let _ = console::Term::stderr().show_cursor();
+}
 
+/// Exit codes by error class, so scripts can branch on the kind of failure:
+///
+///   1   generic error
+///   2   usage or validation error
+///   3   authentication error (not logged in, unauthorized)
+///   4   resource not found
+///   5   network error or timeout
+///   130 cancelled by the user
+fn exit_code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if cause.is::<Cancelled>() {
+            return 130;
+        }
+        if let Some(err) = cause.downcast_ref::<CommandError>() {
+            return match err {
+                CommandError::NotLoggedIn | CommandError::SessionExpired => 3,
+                CommandError::Usage(_) => 2,
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<ApiError>() {
+            return match err {
+                ApiError::Unauthorized => 3,
+                ApiError::NotFound(_) => 4,
+                ApiError::TooManyRequests(_) => 5,
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            match err.status() {
+                Some(StatusCode::UNAUTHORIZED) => return 3,
+                Some(StatusCode::NOT_FOUND) => return 4,
+                _ => {}
+            }
+            if err.is_timeout() || err.is_connect() || err.is_request() {
+                return 5;
+            }
+        }
+    }
+    1
+}
+
+/// Machine readable name for the class of `err`, matching its exit code.
+fn error_kind(err: &anyhow::Error) -> &'static str {
+    let rate_limited = err
+        .chain()
+        .any(|cause| matches!(cause.downcast_ref::<ApiError>(), Some(ApiError::TooManyRequests(_))));
+    if rate_limited {
+        return "rate_limited";
+    }
+    match exit_code(err) {
+        2 => "usage",
+        3 => "unauthorized",
+        4 => "not_found",
+        5 => "network",
+        130 => "cancelled",
+        _ => "error",
+    }
+}
+
+fn run(cli: Cli, matches: &ArgMatches) -> Result<()> {
     if let Some(config_path) = cli.config.as_deref() {
         println!("Config path: {}", config_path);
     }
 
-    let mut config = UserConfig::new(&cli);
-    let mut base = CommandBase::new(&mut config, cli.org);
+    if let Some(Commands::Completions(completions)) = &cli.command {
+        return completions.execute(Cli::command());
+    }
+
+    if let Some(Commands::GenerateMan(generate_man)) = &cli.command {
+        return generate_man.execute(Cli::command());
+    }
+
+    if let Some(Commands::Config(config)) = &cli.command {
+        if let Some(reset) = config.reset() {
+            return reset.execute(&UserConfig::path(&cli)?);
+        }
+    }
+
+    let mut config = UserConfig::new(&cli)?;
+
+    if let Some(Commands::Config(config_cmd)) = &cli.command {
+        if let Some(explain) = config_cmd.explain() {
+            return explain.execute(&cli, matches, &config);
+        }
+    }
+
+    let tls = TlsOptions::new(cli.insecure, cli.ca_cert.clone())?;
+    if tls.insecure {
+        eprintln!(
+            "{} TLS certificate verification is disabled, only use --insecure against servers you trust",
+            style("warning:").yellow().bold().for_stderr()
+        );
+    }
+    let proxy = ProxyOptions::new(cli.proxy.as_deref())?;
+    let mut client = APIClient::new(config.get_url()).with_connection(&tls, &proxy)?;
+    if ResponseCache::enabled() {
+        if let Some(dir) = config.get_path().parent() {
+            client = client.with_cache(ResponseCache::new(dir.join("cache")));
+        }
+    }
+    let api_client: Box<dyn MolnettApi> = if cli.dry_run {
+        Box::new(DryRunClient::new(Box::new(client)))
+    } else {
+        Box::new(client)
+    };
+    let mut base = CommandBase::new(&mut config, api_client, cli.org, tls, proxy);
 
     match cli.command {
         Some(Commands::Auth(auth)) => auth.execute(&mut base),
+        Some(Commands::Completions(_)) => Ok(()),
+        Some(Commands::Config(config)) => config.execute(&mut base),
+        Some(Commands::Context(context)) => context.execute(&mut base),
         Some(Commands::Environments(environments)) => environments.execute(&mut base),
         Some(Commands::Deploy(deploy)) => deploy.execute(&mut base),
         Some(Commands::Logs(logs)) => logs.execute(&mut base),
+        Some(Commands::GenerateMan(_)) => Ok(()),
         Some(Commands::Initialize(init)) => init.execute(&mut base),
         Some(Commands::Orgs(orgs)) => orgs.execute(&mut base),
         Some(Commands::Secrets(secrets)) => secrets.execute(&mut base),
         Some(Commands::Services(svcs)) => svcs.execute(&mut base),
+        Some(Commands::Up(up)) => up.execute(&base),
         None => Ok(()),
     }
 }
@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+/// Parses durations like `30s`, `10m`, `1h` or `2d`. A bare number is read as
+/// seconds and zero is rejected. Meant to be used as a clap `value_parser`.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => input.split_at(idx),
+        None => (input, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(anyhow!(
+                "invalid duration '{}', expected a number followed by s, m, h or d",
+                input
+            ))
+        }
+    };
+    let number: u64 = number.parse().map_err(|err: std::num::ParseIntError| match err.kind() {
+        std::num::IntErrorKind::PosOverflow => anyhow!("duration '{}' is too large", input),
+        _ => anyhow!("invalid duration '{}', expected a number followed by s, m, h or d", input),
+    })?;
+    // A zero timeout or interval would fail or spin right away.
+    if number == 0 {
+        return Err(anyhow!("duration '{}' must be greater than zero", input));
+    }
+    let secs = number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("duration '{}' is too large", input))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Formats a duration the way `parse_duration` reads it, in seconds.
+pub fn format_duration(duration: Duration) -> String {
+    format!("{}s", duration.as_secs())
+}
+
+/// Hides a secret value when displayed, so it can be passed to format strings
+/// and error messages without ending up in the terminal or CI logs.
+pub struct Masked<'a>(pub &'a str);
+
+/// Values shorter than this are too likely to match unrelated text to be
+/// masked inside it, see `Masked::scrub`.
+const MIN_SCRUB_LEN: usize = 8;
+
+impl<'a> Masked<'a> {
+    /// Masks the value of a `KEY=VALUE` pair, keeping the key readable. Input
+    /// without a `=` may be a value passed on its own and is masked whole.
+    pub fn pair(pair: &'a str) -> String {
+        match pair.split_once('=') {
+            Some((key, value)) => format!("{}={}", key, Masked(value)),
+            None => Masked(pair).to_string(),
+        }
+    }
+
+    /// Masks the value wherever it appears in `text`, as is and JSON escaped
+    /// the way an API error body would quote it. Returns `None` when `text`
+    /// contains a value too short to tell apart from the text around it, the
+    /// caller should leave the text out then.
+    pub fn scrub(&self, text: &str) -> Option<String> {
+        if self.0.is_empty() {
+            return Some(text.to_string());
+        }
+        let json = serde_json::to_string(self.0).ok()?;
+        let escaped = &json[1..json.len() - 1];
+        if !text.contains(self.0) && !text.contains(escaped) {
+            return Some(text.to_string());
+        }
+        if self.0.chars().count() < MIN_SCRUB_LEN {
+            return None;
+        }
+        let masked = self.to_string();
+        Some(text.replace(self.0, &masked).replace(escaped, &masked))
+    }
+}
+
+impl std::fmt::Display for Masked<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            f.write_str("****")
+        }
+    }
+}
+
+impl std::fmt::Debug for Masked<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(172800));
+        assert_eq!(parse_duration(" 5m ").unwrap(), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn parse_duration_bare_number_is_seconds() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid_input() {
+        for input in ["", "s", "10x", "10ms", "1.5h", "-5", "h1", "10 m"] {
+            let err = parse_duration(input).unwrap_err();
+            assert!(err.to_string().starts_with("invalid duration"), "{}: {}", input, err);
+        }
+    }
+
+    #[test]
+    fn parse_duration_rejects_zero() {
+        for input in ["0", "0s", "0d"] {
+            assert_eq!(
+                parse_duration(input).unwrap_err().to_string(),
+                format!("duration '{}' must be greater than zero", input)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_duration_rejects_overflow() {
+        for input in ["99999999999999999999", "18446744073709551615m", "213503982334602d"] {
+            let err = parse_duration(input).unwrap_err();
+            assert_eq!(err.to_string(), format!("duration '{}' is too large", input));
+        }
+    }
+
+    #[test]
+    fn format_duration_round_trips() {
+        let duration = parse_duration("2h").unwrap();
+        assert_eq!(parse_duration(&format_duration(duration)).unwrap(), duration);
+    }
+
+    #[test]
+    fn masked_hides_values() {
+        assert_eq!(Masked("hunter2").to_string(), "****");
+        assert_eq!(format!("{:?}", Masked("hunter2")), "\"****\"");
+        // An empty value is shown as empty rather than suggesting one is set.
+        assert_eq!(Masked("").to_string(), "");
+    }
+
+    #[test]
+    fn masked_pair_keeps_the_key() {
+        assert_eq!(Masked::pair("DB_PASSWORD=hunter2"), "DB_PASSWORD=****");
+        assert_eq!(Masked::pair("URL=postgres://u:p@db/x?a=b"), "URL=****");
+        assert_eq!(Masked::pair("EMPTY="), "EMPTY=");
+        assert_eq!(Masked::pair("hunter2"), "****");
+    }
+
+    #[test]
+    fn scrub_masks_plain_and_json_escaped_values() {
+        let value = "pa\"ss\\word";
+        let body = format!("{} is invalid: {{\"value\":{}}}", value, serde_json::to_string(value).unwrap());
+        assert_eq!(Masked(value).scrub(&body).unwrap(), "**** is invalid: {\"value\":\"****\"}");
+        assert_eq!(Masked(value).scrub("invalid value").unwrap(), "invalid value");
+    }
+
+    #[test]
+    fn scrub_drops_text_quoting_short_values() {
+        assert_eq!(Masked("1").scrub("line 1: invalid value 1"), None);
+        assert_eq!(Masked("abc").scrub("invalid value").unwrap(), "invalid value");
+        assert_eq!(Masked("").scrub("invalid value").unwrap(), "invalid value");
+    }
+}